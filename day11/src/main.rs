@@ -1,8 +1,8 @@
 use intcode_computer;
+use intcode_computer::io::{InputSource, OutputSink};
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::convert::TryInto;
-use std::fs::read_to_string;
 use std::ops::AddAssign;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -92,7 +92,7 @@ pub struct Robot {
 
 impl Robot {
     fn init() -> Robot {
-        let code = intcode_computer::parse_program_str(&read_to_string("input.txt").unwrap());
+        let code = intcode_computer::parse_program_file("input.txt").unwrap();
         Robot {
             position: Coordinate { x: 0, y: 0 },
             facing: Direction::Up,
@@ -122,40 +122,46 @@ impl Robot {
         self.position += self.facing.to_coordinate();
     }
 
-    /// returns false when the robot's program has terminated
-    fn move_robot(&mut self) {
-        let output = self.program.run_until_output_or_terminate();
-        if output.is_some() {
-            match output.unwrap() {
-                0 => self.turn_left(),
-                1 => self.turn_right(),
-                x => panic!("invalid movement command received: {}", x),
-            }
-        }
+    pub fn run_on_ship_hull(&mut self, hull: &mut ShipHull) {
+        let mut program = std::mem::replace(&mut self.program, intcode_computer::Program::init(&[]));
+        let mut robot_on_hull = RobotOnHull {
+            robot: self,
+            hull,
+            next_output_is_color: true,
+        };
+        program.run_with(&mut robot_on_hull).unwrap();
+        self.program = program;
     }
+}
 
-    /// returns false when the robot's program has terminated
-    fn paint_color(&mut self, hull: &mut ShipHull) {
-        let output = self.program.run_until_output_or_terminate();
-        if output.is_some() {
-            hull.paint(
-                &self.position,
-                PanelColor::from_i32(output.unwrap().try_into().unwrap()),
-            );
-        }
-    }
+/// Connects the robot's camera and motors to its program, while it moves across the hull.
+struct RobotOnHull<'a> {
+    robot: &'a mut Robot,
+    hull: &'a mut ShipHull,
+    next_output_is_color: bool,
+}
 
-    fn measure_panel_color(&mut self, color: &PanelColor) {
-        self.program.run_until_input(color.to_i32().into());
+impl InputSource for RobotOnHull<'_> {
+    fn next_input(&mut self) -> Option<i64> {
+        return Some(self.hull.get_color(&self.robot.position).to_i32().into());
     }
+}
 
-    pub fn run_on_ship_hull(&mut self, hull: &mut ShipHull) {
-        while !self.program.will_terminate() {
-            self.measure_panel_color(&hull.get_color(&self.position));
-            self.paint_color(hull);
-            self.move_robot();
+impl OutputSink for RobotOnHull<'_> {
+    fn write_output(&mut self, value: i64) {
+        if self.next_output_is_color {
+            self.hull.paint(
+                &self.robot.position,
+                PanelColor::from_i32(value.try_into().unwrap()),
+            );
+        } else {
+            match value {
+                0 => self.robot.turn_left(),
+                1 => self.robot.turn_right(),
+                x => panic!("invalid movement command received: {}", x),
+            }
         }
-        self.measure_panel_color(&hull.get_color(&self.position));
+        self.next_output_is_color = !self.next_output_is_color;
     }
 }
 
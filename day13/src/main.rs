@@ -1,7 +1,6 @@
-use intcode_computer::Program;
+use intcode_computer::{Program, RunState};
 use std::collections::BTreeMap;
 use std::fmt;
-use std::fs::read_to_string;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Tile {
@@ -101,14 +100,14 @@ impl Arcade {
         let mut outputs = Vec::new();
         let mut redraw_visualization = false;
         loop {
-            match self.program.next_opcode() {
-                intcode_computer::Opcode::Terminate => return (redraw_visualization, false),
-                intcode_computer::Opcode::Output => outputs.push(self.program.step(None).unwrap()),
-                intcode_computer::Opcode::Input => {
-                    assert_eq!(self.program.step(Some(self.get_paddle_direction())).is_none(), true);
+            match self.program.resume().unwrap() {
+                RunState::Halted => return (redraw_visualization, false),
+                RunState::Output(value) => outputs.push(value),
+                RunState::Yielded => (),
+                RunState::NeedsInput => {
+                    self.program.provide_input(self.get_paddle_direction());
                     redraw_visualization = true;
                 }
-                _ => assert_eq!(self.program.step(None).is_none(), true),
             };
 
             if outputs.len() == 3 {
@@ -127,12 +126,12 @@ impl Arcade {
 
     pub fn run(&mut self) {
         loop {
-            let x = match self.program.run_until_output_or_terminate() {
+            let x = match self.program.run_until_output_or_terminate().unwrap() {
                 Some(x) => x,
                 None => break, // breaks if program terminates
             };
-            let y = self.program.run_until_output_or_terminate().unwrap();
-            let id = self.program.run_until_output_or_terminate().unwrap();
+            let y = self.program.run_until_output_or_terminate().unwrap().unwrap();
+            let id = self.program.run_until_output_or_terminate().unwrap().unwrap();
             self.set_tile(
                 Vec2::new(x as usize, y as usize),
                 Tile::from_id(id as usize),
@@ -175,7 +174,7 @@ fn load_code_with_free_play() -> Vec<i64> {
 }
 
 fn load_code() -> Vec<i64> {
-    return intcode_computer::parse_program_str(&read_to_string("input.txt").unwrap());
+    return intcode_computer::parse_program_file("input.txt").unwrap();
 }
 
 fn main() {
@@ -1,31 +1,19 @@
 use intcode_computer;
-use std::fs::read_to_string;
+use intcode_computer::solver::{self, Observed, Patch};
 
 fn main() {
     println!("loading initial state:");
-    let code = intcode_computer::parse_program_str(&read_to_string("input.txt").unwrap());
+    let code = intcode_computer::parse_program_file("input.txt").unwrap();
     let basic_program = intcode_computer::Program::init(&code);
-    'outer: for noun in 0..100 {
-        for verb in 0..100 {
-            let mut altered_program = basic_program.clone();
-            altered_program.set_memory(1, noun);
-            altered_program.set_memory(2, verb);
-            //println!("Altered state:\n{:?}", altered_state);
-            altered_program.run(Vec::new());
-            //println!("Final state:\n{:?}", altered_state);
-            let result = altered_program.read_memory(0);
-            println!(
-                "With noun {} and verb {}: result is {}.",
-                noun, verb, result
-            );
-            if result == 19690720 {
-                println!(
-                    "Found a correct noun, verb combination! 100 * noun + verb = {}",
-                    100 * noun + verb
-                );
-                break 'outer;
-            }
-        }
+    let noun = Patch { address: 1, values: 0..=99 };
+    let verb = Patch { address: 2, values: 0..=99 };
+    let solution = solver::solve(&basic_program, &[noun, verb], Observed::Memory(0), 19690720).unwrap();
+    println!("Searched noun and verb using strategy {:?}.", solution.strategy);
+    for values in solution.matches {
+        println!(
+            "Found a correct noun, verb combination! 100 * noun + verb = {}",
+            100 * values[0] + values[1]
+        );
     }
 }
 
@@ -41,6 +29,6 @@ fn day2_works() {
         13, 147, 151, 1, 2, 151, 155, 1, 10, 155, 0, 99, 2, 14, 0, 0,
     ];
     let mut program = intcode_computer::Program::init(&program_state.to_vec());
-    program.run(Vec::new());
+    program.run(Vec::new()).unwrap();
     assert_eq!(program.read_memory(0), 19690720);
 }
@@ -1,6 +1,8 @@
 use intcode_computer;
+use intcode_computer::threaded;
+use intcode_computer::Program;
 use permutohedron::Heap;
-use std::fs::read_to_string;
+use std::sync::mpsc::{channel, Receiver, Sender};
 
 fn main() {
     //day7();
@@ -10,7 +12,7 @@ fn main() {
 #[allow(dead_code)]
 fn day7() {
     println!("loading initial state:");
-    let code = intcode_computer::parse_program_str(&read_to_string("input.txt").unwrap());
+    let code = intcode_computer::parse_program_file("input.txt").unwrap();
     let mut max_thruster_value = 0;
     let mut phase_settings = [0, 1, 2, 3, 4];
     for phase_settings_permutation in Heap::new(&mut phase_settings).by_ref() {
@@ -28,7 +30,7 @@ fn day7() {
 
 fn day7_part2() {
     println!("loading initial state:");
-    let code = intcode_computer::parse_program_str(&read_to_string("input.txt").unwrap());
+    let code = intcode_computer::parse_program_file("input.txt").unwrap();
     let mut max_thruster_value = 0;
     let mut phase_settings = [5, 6, 7, 8, 9];
     for phase_settings_permutation in Heap::new(&mut phase_settings).by_ref() {
@@ -47,47 +49,34 @@ fn day7_part2() {
 
 /// For a given program and a set of phase settings, calculate the resulting thruster value
 fn amplification_circuit_with_feedback(program_state: &[i64], phase_settings: [i64; 5]) -> i64 {
-    let mut amplifier_programs = [
-        intcode_computer::Program::init(program_state),
-        intcode_computer::Program::init(program_state),
-        intcode_computer::Program::init(program_state),
-        intcode_computer::Program::init(program_state),
-        intcode_computer::Program::init(program_state),
-    ];
+    let (input_senders, input_receivers): (Vec<Sender<i64>>, Vec<Receiver<i64>>) =
+        phase_settings.iter().map(|_| channel()).unzip();
     // set amplifiers' phases
     for (amplifier_id, phase) in phase_settings.iter().enumerate() {
         println!("Setting phase of amplifier {} to {}", amplifier_id, phase);
-        amplifier_programs[amplifier_id].run_until_input(*phase);
+        input_senders[amplifier_id].send(*phase).unwrap();
     }
-    // run until amplifiers terminate
-    let mut not_terminated = true;
-    let mut previous_output = 0;
-    let mut feedback_loop_counter = 0;
-    while not_terminated {
-        for (amplifier_id, program) in amplifier_programs.iter_mut().enumerate() {
-            if program.will_terminate() {
-                not_terminated = false;
-                println!(
-                    "#{} - Amplifier {} terminated.",
-                    feedback_loop_counter, amplifier_id
-                );
-            } else {
-                program.run_until_input(previous_output);
-                let new_output = program.run_until_output_or_terminate();
-                assert_eq!(new_output.is_some(), true);
-                println!(
-                    "#{} - Amplifier {}: {} -> {}.",
-                    feedback_loop_counter,
-                    amplifier_id,
-                    previous_output,
-                    new_output.unwrap(),
-                );
-                previous_output = new_output.unwrap();
-            }
-        }
-        feedback_loop_counter += 1;
+    input_senders[0].send(0).unwrap();
+    // each amplifier sends its output to the next one, the last one's output is fed back by this thread
+    let (thruster_sender, thruster_receiver) = channel();
+    let mut output_senders = input_senders[1..].to_vec();
+    output_senders.push(thruster_sender);
+    let amplifiers: Vec<_> = input_receivers
+        .into_iter()
+        .zip(output_senders)
+        .map(|(input, output)| threaded::spawn(Program::init(program_state), input, output))
+        .collect();
+    let mut thruster_value = 0;
+    for (feedback_loop_counter, output) in thruster_receiver.iter().enumerate() {
+        println!("#{} - thruster value: {}", feedback_loop_counter, output);
+        thruster_value = output;
+        // fails once the first amplifier terminated, which is fine
+        let _ = input_senders[0].send(output);
+    }
+    for amplifier in amplifiers {
+        amplifier.join().unwrap().unwrap();
     }
-    return previous_output; // thruster value
+    return thruster_value;
 }
 
 /// For a given program and a set of phase settings, calculate the resulting thruster value
@@ -96,7 +85,7 @@ fn amplification_circuit(program_state: &[i64], phase_settings: [i64; 5]) -> i64
     for (_amplifier_id, phase) in phase_settings.iter().enumerate() {
         let mut amplifier_program = intcode_computer::Program::init(program_state);
         let input = [previous_output, phase.to_owned()];
-        previous_output = amplifier_program.run(input.to_vec())[0];
+        previous_output = amplifier_program.run(input.to_vec()).unwrap()[0];
         //println!(
         //    "Amplifier {}: phase setting {} | {} -> {}",
         //    _amplifier_id, input[1], input[0], previous_output
@@ -131,7 +120,7 @@ fn get_thruster_signal() {
 
 #[test]
 fn day7_part1_works() {
-    let code = intcode_computer::parse_program_str(&read_to_string("input.txt").unwrap());
+    let code = intcode_computer::parse_program_file("input.txt").unwrap();
     let mut max_thruster_value = 0;
     let mut phase_settings = [0, 1, 2, 3, 4];
     for phase_settings_permutation in Heap::new(&mut phase_settings).by_ref() {
@@ -149,7 +138,7 @@ fn day7_part1_works() {
 
 #[test]
 fn day7_part2_works() {
-    let code = intcode_computer::parse_program_str(&read_to_string("input.txt").unwrap());
+    let code = intcode_computer::parse_program_file("input.txt").unwrap();
     let mut max_thruster_value = 0;
     let mut phase_settings = [5, 6, 7, 8, 9];
     for phase_settings_permutation in Heap::new(&mut phase_settings).by_ref() {
@@ -0,0 +1,47 @@
+use crate::{Memory, Opcode, ParameterMode, Program};
+
+/// The memory an instruction is about to access, determined before executing it.
+/// Reads only include parameters in position or relative mode, not the instruction's own words.
+pub(crate) struct InstructionAccesses {
+    pub(crate) instruction_pointer: usize,
+    pub(crate) opcode: Opcode,
+    pub(crate) length: usize,
+    pub(crate) reads: Vec<usize>,
+    pub(crate) write: Option<usize>,
+}
+
+impl InstructionAccesses {
+    /// Returns None if the instruction can't be decoded or a parameter can't be resolved, as executing it will fail anyway.
+    /// Custom opcodes don't necessarily resolve all their parameters, those that can't be resolved are left out.
+    pub(crate) fn inspect<M: Memory>(program: &Program<M>) -> Option<InstructionAccesses> {
+        let instruction_pointer = program.instruction_pointer;
+        let (opcode, pm1, pm2, pm3) = program.peek_instruction().ok()?;
+        let modes = [pm1, pm2, pm3];
+        let is_custom = matches!(opcode, Opcode::Custom(_));
+        let mut reads = Vec::new();
+        let mut write = None;
+        for parameter_id in 1..=opcode.parameter_count() {
+            let mode = modes[parameter_id - 1];
+            let address = if opcode.result_parameter() == Some(parameter_id) {
+                program.resolve_parameter_to_result_address(parameter_id, mode)
+            } else if mode != ParameterMode::Immediate {
+                program.resolve_parameter_to_address(parameter_id, mode)
+            } else {
+                continue;
+            };
+            match address {
+                Ok(address) if opcode.result_parameter() == Some(parameter_id) => write = Some(address),
+                Ok(address) => reads.push(address),
+                Err(_) if is_custom => (),
+                Err(_) => return None,
+            }
+        }
+        return Some(InstructionAccesses {
+            instruction_pointer,
+            opcode,
+            length: opcode.parameter_count() + 1,
+            reads,
+            write,
+        });
+    }
+}
@@ -0,0 +1,112 @@
+//! Helpers for programs that read and write text, one ASCII character per input or output value.
+use crate::io::OutputSink;
+use crate::{IntcodeError, Memory, Program};
+use std::collections::VecDeque;
+
+/// Encodes a line of text into input values, terminated by a newline.
+/// The text should be ASCII, other characters are encoded as their UTF-8 bytes.
+pub fn encode_line(line: &str) -> Vec<i64> {
+    let mut values: Vec<i64> = line.bytes().map(i64::from).collect();
+    values.push(i64::from(b'\n'));
+    return values;
+}
+
+/// Outputs of a program, split into text and values that aren't ASCII characters.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct AsciiOutput {
+    pub text: String,
+    /// Outputs outside of 0 to 127, in the order they were produced.
+    pub values: Vec<i64>,
+}
+
+impl OutputSink for AsciiOutput {
+    fn write_output(&mut self, value: i64) {
+        match value {
+            0..=127 => self.text.push(value as u8 as char),
+            _ => self.values.push(value),
+        }
+    }
+}
+
+pub fn decode(outputs: &[i64]) -> AsciiOutput {
+    let mut ascii_output = AsciiOutput::default();
+    for output in outputs {
+        ascii_output.write_output(*output);
+    }
+    return ascii_output;
+}
+
+impl<M: Memory> Program<M> {
+    /// Runs the program until it terminates, feeding it the given lines of text. Each line is terminated by a newline.
+    pub fn run_ascii(&mut self, lines: &[&str]) -> Result<AsciiOutput, IntcodeError> {
+        let input: VecDeque<i64> = lines.iter().flat_map(|line| encode_line(line)).collect();
+        let mut io = (input, AsciiOutput::default());
+        self.run_with(&mut io)?;
+        return Ok(io.1);
+    }
+
+    /// Queues a line of text for `resume`, terminated by a newline.
+    pub fn provide_ascii_line(&mut self, line: &str) {
+        for value in encode_line(line) {
+            self.provide_input(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::RunState;
+
+    // echoes lines until an empty one is read, then outputs 1000 plus the number of lines
+    const ECHO: &str = "
+        loop:   IN [char]
+                OUT [char]
+                EQ [char], #10, [newline]
+                JZ [newline], #no_newline
+                JNZ [empty], #done
+                ADD [lines], #1, [lines]
+                ADD #1, #0, [empty]
+                JNZ #1, #loop
+        no_newline: ADD #0, #0, [empty]
+                JNZ #1, #loop
+        done:   OUT [lines]
+                HLT
+        char:   .data 0
+        newline: .data 0
+        empty:  .data 1
+        lines:  .data 1000
+    ";
+
+    #[test]
+    fn encode_and_decode() {
+        assert_eq!(encode_line("Hi!"), [72, 105, 33, 10]);
+        assert_eq!(encode_line(""), [10]);
+        assert_eq!(
+            decode(&[79, 75, 10, 1000, 33, -1]),
+            AsciiOutput {
+                text: "OK\n!".to_string(),
+                values: vec![1000, -1],
+            }
+        );
+    }
+
+    #[test]
+    fn run_ascii_program() {
+        let code = assemble(ECHO).unwrap();
+        let mut program = Program::init(&code);
+        let output = program.run_ascii(&["NOT A J", "WALK", ""]).unwrap();
+        assert_eq!(output.text, "NOT A J\nWALK\n\n");
+        assert_eq!(output.values, [1002]);
+    }
+
+    #[test]
+    fn provide_ascii_line() {
+        let mut program = Program::init(&assemble(ECHO).unwrap());
+        program.provide_ascii_line("A");
+        assert_eq!(program.resume(), Ok(RunState::Output(65)));
+        assert_eq!(program.resume(), Ok(RunState::Output(10)));
+        assert_eq!(program.resume(), Ok(RunState::NeedsInput));
+    }
+}
@@ -0,0 +1,305 @@
+//! Assembles Intcode from a textual representation.
+//!
+//! Every line holds an optional label, followed by an instruction or a `.data` directive:
+//!
+//! ```text
+//! # prints 1 if the input is less than 8, 0 otherwise
+//! start:  IN [x]
+//!         LT [x], #8, [x]
+//!         OUT [x]
+//!         HLT
+//! x:      .data 0
+//! ```
+//!
+//! Mnemonics are the ones used by the disassembler. Parameters are written as `[address]` (position mode),
+//! `#value` (immediate mode) or `rb+offset` (relative mode). Addresses and values may be labels.
+//! Comments start with `;`, or with `#` at the beginning of a line.
+use crate::{encode_instruction, Opcode, ParameterMode};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum AssembleErrorKind {
+    UnknownMnemonic(String),
+    UnknownDirective(String),
+    WrongParameterCount { expected: usize, found: usize },
+    InvalidParameter(String),
+    InvalidValue(String),
+    ImmediateModeResult,
+    InvalidLabel(String),
+    DuplicateLabel(String),
+    UnknownLabel(String),
+}
+
+impl fmt::Display for AssembleErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssembleErrorKind::UnknownMnemonic(mnemonic) => write!(f, "unknown mnemonic '{}'", mnemonic),
+            AssembleErrorKind::UnknownDirective(directive) => write!(f, "unknown directive '{}'", directive),
+            AssembleErrorKind::WrongParameterCount { expected, found } => {
+                write!(f, "expected {} parameters, found {}", expected, found)
+            }
+            AssembleErrorKind::InvalidParameter(parameter) => write!(f, "invalid parameter '{}'", parameter),
+            AssembleErrorKind::InvalidValue(value) => write!(f, "invalid value '{}'", value),
+            AssembleErrorKind::ImmediateModeResult => write!(f, "result parameter can't use immediate mode"),
+            AssembleErrorKind::InvalidLabel(label) => write!(f, "invalid label '{}'", label),
+            AssembleErrorKind::DuplicateLabel(label) => write!(f, "label '{}' is defined twice", label),
+            AssembleErrorKind::UnknownLabel(label) => write!(f, "unknown label '{}'", label),
+        }
+    }
+}
+
+/// Error returned when assembling fails, naming the (1-based) line it failed on.
+#[derive(Debug, PartialEq, Clone)]
+pub struct AssembleError {
+    pub line: usize,
+    pub kind: AssembleErrorKind,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl Error for AssembleError {}
+
+enum Value {
+    Number(i64),
+    Label(String),
+}
+
+struct Parameter {
+    mode: ParameterMode,
+    value: Value,
+}
+
+enum Statement {
+    Instruction { opcode: Opcode, parameters: Vec<Parameter> },
+    Data(Vec<Value>),
+}
+
+impl Statement {
+    fn len(&self) -> usize {
+        return match self {
+            Statement::Instruction { parameters, .. } => parameters.len() + 1,
+            Statement::Data(values) => values.len(),
+        };
+    }
+}
+
+/// Assembles the given source into code, which can be loaded via `Program::init`.
+pub fn assemble(source: &str) -> Result<Vec<i64>, AssembleError> {
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = 0;
+    for (line_id, line) in source.lines().enumerate() {
+        let error = |kind| AssembleError { line: line_id + 1, kind };
+        let mut rest = strip_comment(line).trim();
+        while let Some(colon) = rest.find(':') {
+            let label = rest[..colon].trim();
+            if !is_label(label) {
+                return Err(error(AssembleErrorKind::InvalidLabel(label.to_string())));
+            }
+            if labels.insert(label.to_string(), address as i64).is_some() {
+                return Err(error(AssembleErrorKind::DuplicateLabel(label.to_string())));
+            }
+            rest = rest[colon + 1..].trim();
+        }
+        if rest.is_empty() {
+            continue;
+        }
+        let statement = parse_statement(rest).map_err(error)?;
+        address += statement.len();
+        statements.push((line_id + 1, statement));
+    }
+
+    let mut code = Vec::with_capacity(address);
+    for (line, statement) in statements {
+        let resolve = |value: &Value| match value {
+            Value::Number(number) => Ok(*number),
+            Value::Label(label) => labels.get(label).cloned().ok_or_else(|| AssembleError {
+                line,
+                kind: AssembleErrorKind::UnknownLabel(label.clone()),
+            }),
+        };
+        match statement {
+            Statement::Instruction { opcode, parameters } => {
+                code.push(encode_instruction(opcode, parameters.iter().map(|parameter| parameter.mode)));
+                for parameter in parameters.iter() {
+                    code.push(resolve(&parameter.value)?);
+                }
+            }
+            Statement::Data(values) => {
+                for value in values.iter() {
+                    code.push(resolve(value)?);
+                }
+            }
+        }
+    }
+    return Ok(code);
+}
+
+fn strip_comment(line: &str) -> &str {
+    if line.trim_start().starts_with('#') {
+        return "";
+    }
+    return line.split(';').next().unwrap_or("");
+}
+
+fn parse_statement(statement: &str) -> Result<Statement, AssembleErrorKind> {
+    let (keyword, arguments) = match statement.find(char::is_whitespace) {
+        Some(position) => (&statement[..position], statement[position..].trim()),
+        None => (statement, ""),
+    };
+    let arguments: Vec<&str> = if arguments.is_empty() {
+        Vec::new()
+    } else {
+        arguments.split(',').map(|argument| argument.trim()).collect()
+    };
+    if keyword.starts_with('.') {
+        if keyword != ".data" {
+            return Err(AssembleErrorKind::UnknownDirective(keyword.to_string()));
+        }
+        let values = arguments.iter().map(|argument| parse_value(argument)).collect::<Result<_, _>>()?;
+        return Ok(Statement::Data(values));
+    }
+    let opcode = Opcode::from_mnemonic(keyword).ok_or_else(|| AssembleErrorKind::UnknownMnemonic(keyword.to_string()))?;
+    if arguments.len() != opcode.parameter_count() {
+        return Err(AssembleErrorKind::WrongParameterCount {
+            expected: opcode.parameter_count(),
+            found: arguments.len(),
+        });
+    }
+    let parameters: Vec<Parameter> = arguments.iter().map(|argument| parse_parameter(argument)).collect::<Result<_, _>>()?;
+    if let Some(result_parameter) = opcode.result_parameter() {
+        if parameters[result_parameter - 1].mode == ParameterMode::Immediate {
+            return Err(AssembleErrorKind::ImmediateModeResult);
+        }
+    }
+    return Ok(Statement::Instruction { opcode, parameters });
+}
+
+fn parse_parameter(parameter: &str) -> Result<Parameter, AssembleErrorKind> {
+    let invalid = || AssembleErrorKind::InvalidParameter(parameter.to_string());
+    if parameter.starts_with('[') && parameter.ends_with(']') {
+        return Ok(Parameter {
+            mode: ParameterMode::Position,
+            value: parse_value(parameter[1..parameter.len() - 1].trim())?,
+        });
+    }
+    if let Some(value) = parameter.strip_prefix('#') {
+        return Ok(Parameter {
+            mode: ParameterMode::Immediate,
+            value: parse_value(value.trim())?,
+        });
+    }
+    if let Some(offset) = parameter.strip_prefix("rb") {
+        let offset: String = offset.chars().filter(|c| !c.is_whitespace()).collect();
+        if !offset.starts_with('+') && !offset.starts_with('-') {
+            return Err(invalid());
+        }
+        let value = match offset.strip_prefix('+') {
+            Some(offset) => parse_value(offset)?,
+            None => parse_value(&offset)?,
+        };
+        return Ok(Parameter { mode: ParameterMode::Relative, value });
+    }
+    return Err(invalid());
+}
+
+fn parse_value(value: &str) -> Result<Value, AssembleErrorKind> {
+    if let Ok(number) = value.parse::<i64>() {
+        return Ok(Value::Number(number));
+    }
+    if is_label(value) {
+        return Ok(Value::Label(value.to_string()));
+    }
+    return Err(AssembleErrorKind::InvalidValue(value.to_string()));
+}
+
+fn is_label(label: &str) -> bool {
+    return match label.chars().next() {
+        Some(first) if first.is_alphabetic() || first == '_' => {
+            label.chars().all(|c| c.is_alphanumeric() || c == '_')
+        }
+        _ => false,
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::disassembler::disassemble;
+    use crate::Program;
+
+    #[test]
+    fn assemble_instructions() {
+        let code = assemble("ADD #100, #-1, [4]\nHLT").unwrap();
+        assert_eq!(code, [1101, 100, -1, 4, 99]);
+        let code = assemble("arb #19\nout rb-8\nhlt").unwrap();
+        assert_eq!(code, [109, 19, 204, -8, 99]);
+    }
+
+    #[test]
+    fn assemble_with_labels_and_data() {
+        let source = "
+            # prints 1 if the input is less than 8, 0 otherwise
+            start:  IN [x]
+                    LT [x], #8, [x]  ; compare
+                    OUT [x]
+                    JZ #0, #end
+                    JNZ #1, #start
+            end:    HLT
+            x:      .data 0
+            table:  .data 1, -2, table
+        ";
+        let code = assemble(source).unwrap();
+        assert_eq!(code, [3, 15, 1007, 15, 8, 15, 4, 15, 1106, 0, 14, 1105, 1, 0, 99, 0, 1, -2, 16]);
+    }
+
+    #[test]
+    fn assembled_program_runs() {
+        let source = "
+                    IN [x]
+                    LT [x], #8, [x]
+                    OUT [x]
+                    HLT
+            x:      .data 0
+        ";
+        let code = assemble(source).unwrap();
+        for (input, expected) in [(7, 1), (8, 0)].iter() {
+            let mut program = Program::init(&code);
+            assert_eq!(program.run(vec![*input]).unwrap(), [*expected]);
+        }
+    }
+
+    #[test]
+    fn disassembly_round_trip() {
+        let code = [109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99];
+        let source: Vec<String> = disassemble(&code).iter().map(|line| line.decoded.to_string()).collect();
+        assert_eq!(assemble(&source.join("\n")).unwrap(), code);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = |source| assemble(source).unwrap_err();
+        assert_eq!(
+            error("HLT\nFOO [1]"),
+            AssembleError { line: 2, kind: AssembleErrorKind::UnknownMnemonic("FOO".to_string()) }
+        );
+        assert_eq!(
+            error("ADD [1], [2]").kind,
+            AssembleErrorKind::WrongParameterCount { expected: 3, found: 2 }
+        );
+        assert_eq!(error("\n\nIN #3").line, 3);
+        assert_eq!(error("IN #3").kind, AssembleErrorKind::ImmediateModeResult);
+        assert_eq!(error("OUT (3)").kind, AssembleErrorKind::InvalidParameter("(3)".to_string()));
+        assert_eq!(error("JNZ #1, #nowhere").kind, AssembleErrorKind::UnknownLabel("nowhere".to_string()));
+        assert_eq!(error("a: HLT\na: HLT").kind, AssembleErrorKind::DuplicateLabel("a".to_string()));
+        assert_eq!(error(".word 3").kind, AssembleErrorKind::UnknownDirective(".word".to_string()));
+        assert_eq!(error("OUT #1x").kind, AssembleErrorKind::InvalidValue("1x".to_string()));
+        assert_eq!(error("HLT\n1st: HLT").line, 2);
+    }
+}
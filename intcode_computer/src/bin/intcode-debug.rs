@@ -0,0 +1,455 @@
+#![allow(clippy::needless_return)]
+use intcode_computer::cfg;
+use intcode_computer::decompiler::decompile;
+use intcode_computer::disassembler::disassemble_at;
+use intcode_computer::{parse_program_file, Opcode, Program};
+use std::collections::{BTreeSet, VecDeque};
+use std::env;
+use std::fs::write;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+commands:
+  load <file>            load a program and reset the debugger
+  reset                  restart the loaded program, keeping breakpoints
+  save <file>            write a snapshot of the program's state
+  restore <file>         continue from a snapshot written by save
+  cfg <file>             write the control-flow graph of the loaded program as Graphviz DOT
+  decompile <file>       write C-like pseudocode of the loaded program
+  break <address>        set a breakpoint (b)
+  clear <address>        remove a breakpoint
+  breakpoints            list all breakpoints
+  step [count]           execute one or more instructions (s)
+  continue               run until a breakpoint, input is needed or the program halts (c)
+  back [count]           undo one or more instructions (sb)
+  reverse                run backwards until a breakpoint or the start of the undo history (rc)
+  instruction            print the instruction at the instruction pointer (i)
+  memory <start> [end]   dump memory, end is exclusive (m)
+  registers              print instruction pointer and relative base (r)
+  profile [count]        print execution counts and the hottest instructions (default 10)
+  input <value>...       queue values for input instructions
+  help                   print this help
+  quit                   exit the debugger (q)";
+
+/// Number of instructions that can be stepped back.
+const UNDO_STEPS: usize = 1_000_000;
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Load(String),
+    Reset,
+    Save(String),
+    Restore(String),
+    Cfg(String),
+    Decompile(String),
+    Break(usize),
+    Clear(usize),
+    Breakpoints,
+    Step(usize),
+    Continue,
+    Back(usize),
+    Reverse,
+    Instruction,
+    Memory(usize, usize),
+    Registers,
+    Profile(usize),
+    Input(Vec<i64>),
+    Help,
+    Quit,
+}
+
+fn parse_number<T: std::str::FromStr>(argument: Option<&&str>) -> Result<T, String> {
+    let argument = argument.ok_or("missing argument")?;
+    return argument.parse().map_err(|_| format!("invalid number '{}'", argument));
+}
+
+fn parse_command(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let command = match words.first() {
+        Some(command) => *command,
+        None => return Err("empty command".to_string()),
+    };
+    return match command {
+        "load" => Ok(Command::Load(words.get(1).ok_or("missing file name")?.to_string())),
+        "reset" => Ok(Command::Reset),
+        "save" => Ok(Command::Save(words.get(1).ok_or("missing file name")?.to_string())),
+        "restore" => Ok(Command::Restore(words.get(1).ok_or("missing file name")?.to_string())),
+        "cfg" => Ok(Command::Cfg(words.get(1).ok_or("missing file name")?.to_string())),
+        "decompile" => Ok(Command::Decompile(words.get(1).ok_or("missing file name")?.to_string())),
+        "break" | "b" => Ok(Command::Break(parse_number(words.get(1))?)),
+        "clear" => Ok(Command::Clear(parse_number(words.get(1))?)),
+        "breakpoints" => Ok(Command::Breakpoints),
+        "step" | "s" => match words.get(1) {
+            Some(_) => Ok(Command::Step(parse_number(words.get(1))?)),
+            None => Ok(Command::Step(1)),
+        },
+        "continue" | "c" => Ok(Command::Continue),
+        "back" | "sb" => match words.get(1) {
+            Some(_) => Ok(Command::Back(parse_number(words.get(1))?)),
+            None => Ok(Command::Back(1)),
+        },
+        "reverse" | "rc" => Ok(Command::Reverse),
+        "instruction" | "i" => Ok(Command::Instruction),
+        "memory" | "m" => {
+            let start: usize = parse_number(words.get(1))?;
+            let end = match words.get(2) {
+                Some(_) => parse_number(words.get(2))?,
+                None => start.saturating_add(1),
+            };
+            Ok(Command::Memory(start, end))
+        }
+        "registers" | "r" => Ok(Command::Registers),
+        "profile" => match words.get(1) {
+            Some(_) => Ok(Command::Profile(parse_number(words.get(1))?)),
+            None => Ok(Command::Profile(10)),
+        },
+        "input" => {
+            let values = words[1..]
+                .iter()
+                .map(|value| parse_number(Some(value)))
+                .collect::<Result<Vec<i64>, String>>()?;
+            Ok(Command::Input(values))
+        }
+        "help" | "h" => Ok(Command::Help),
+        "quit" | "q" => Ok(Command::Quit),
+        unknown => Err(format!("unknown command '{}', try 'help'", unknown)),
+    };
+}
+
+/// Why the debugger stopped executing instructions.
+#[derive(Debug, PartialEq)]
+enum Stop {
+    Stepped,
+    Breakpoint,
+    NeedsInput,
+    Halted,
+    Failed,
+    HistoryStart,
+}
+
+struct Debugger {
+    code: Vec<i64>,
+    /// The program `reset` returns to, either the loaded code or a restored snapshot.
+    initial: Program,
+    program: Program,
+    breakpoints: BTreeSet<usize>,
+    inputs: VecDeque<i64>,
+}
+
+impl Debugger {
+    fn new(code: Vec<i64>) -> Debugger {
+        let initial = Program::init(&code);
+        return Debugger {
+            program: Debugger::instrumented_program(initial.clone()),
+            initial,
+            code,
+            breakpoints: BTreeSet::new(),
+            inputs: VecDeque::new(),
+        };
+    }
+
+    fn instrumented_program(mut program: Program) -> Program {
+        program.enable_profiling();
+        program.enable_undo_log(UNDO_STEPS);
+        return program;
+    }
+
+    /// Continues with the given program, its pending inputs replace the queued ones.
+    fn start(&mut self, mut program: Program) {
+        self.inputs = program.take_pending_inputs();
+        self.program = Debugger::instrumented_program(program);
+    }
+
+    /// Executes a command, returns the text to print, or None if the debugger should quit.
+    fn execute(&mut self, command: Command) -> Option<String> {
+        let text = match command {
+            Command::Load(file_name) => match parse_program_file(&file_name) {
+                Ok(code) => {
+                    *self = Debugger::new(code);
+                    format!("loaded {} words from {}", self.code.len(), file_name)
+                }
+                Err(error) => format!("couldn't load {}: {}", file_name, error),
+            },
+            Command::Reset => {
+                self.start(self.initial.clone());
+                "program reset".to_string()
+            }
+            Command::Save(file_name) => {
+                // queued inputs are saved as the snapshot's pending inputs
+                let mut program = self.program.clone();
+                for input in self.inputs.iter() {
+                    program.provide_input(*input);
+                }
+                match program.save_snapshot_file(&file_name) {
+                    Ok(()) => format!("snapshot written to {}", file_name),
+                    Err(error) => format!("couldn't write {}: {}", file_name, error),
+                }
+            }
+            Command::Restore(file_name) => match Program::load_snapshot_file(&file_name) {
+                Ok(program) => {
+                    // cfg and decompile look at the restored memory, resetting returns to the snapshot
+                    self.code = program.memory_as_vec();
+                    self.initial = program.clone();
+                    self.start(program);
+                    format!("restored snapshot from {}\n{}", file_name, self.current_instruction())
+                }
+                Err(error) => format!("couldn't restore {}: {}", file_name, error),
+            },
+            Command::Cfg(file_name) => {
+                let graph = cfg::extract(&self.code);
+                match write(&file_name, graph.to_dot()) {
+                    Ok(()) => format!(
+                        "{} blocks ({} with unresolved jumps) written to {}",
+                        graph.blocks.len(),
+                        graph.indirect_jumps().len(),
+                        file_name
+                    ),
+                    Err(error) => format!("couldn't write {}: {}", file_name, error),
+                }
+            }
+            Command::Decompile(file_name) => match write(&file_name, decompile(&self.code)) {
+                Ok(()) => format!("pseudocode written to {}", file_name),
+                Err(error) => format!("couldn't write {}: {}", file_name, error),
+            },
+            Command::Break(address) => {
+                self.breakpoints.insert(address);
+                format!("breakpoint set at {}", address)
+            }
+            Command::Clear(address) => match self.breakpoints.remove(&address) {
+                true => format!("breakpoint at {} removed", address),
+                false => format!("no breakpoint at {}", address),
+            },
+            Command::Breakpoints => {
+                let addresses: Vec<String> = self.breakpoints.iter().map(|a| a.to_string()).collect();
+                format!("breakpoints: {}", addresses.join(", "))
+            }
+            Command::Step(count) => self.run(Some(count)),
+            Command::Continue => self.run(None),
+            Command::Back(count) => self.run_backwards(Some(count)),
+            Command::Reverse => self.run_backwards(None),
+            Command::Instruction => self.current_instruction(),
+            Command::Memory(start, end) => self.dump_memory(start, end),
+            Command::Registers => format!(
+                "instruction pointer: {}, relative base: {}",
+                self.program.instruction_pointer(),
+                self.program.relative_base()
+            ),
+            Command::Profile(count) => self.program.profile().unwrap().report(&self.program, count),
+            Command::Input(values) => {
+                self.inputs.extend(values);
+                format!("{} inputs queued", self.inputs.len())
+            }
+            Command::Help => HELP.to_string(),
+            Command::Quit => return None,
+        };
+        return Some(text);
+    }
+
+    fn current_instruction(&self) -> String {
+        let marker = if self.breakpoints.contains(&self.program.instruction_pointer()) { "*" } else { " " };
+        return format!("{}{}", marker, disassemble_at(&self.program, self.program.instruction_pointer()));
+    }
+
+    fn dump_memory(&self, start: usize, end: usize) -> String {
+        let mut lines = Vec::new();
+        for line_start in (start..end).step_by(8) {
+            let values: Vec<String> = (line_start..end.min(line_start.saturating_add(8)))
+                .map(|address| format!("{:>8}", self.program.read_memory(address)))
+                .collect();
+            lines.push(format!("{:>6}: {}", line_start, values.join(" ")));
+        }
+        return lines.join("\n");
+    }
+
+    /// Undoes the given number of instructions, or until a breakpoint is reached if no count is given.
+    /// Inputs consumed by undone instructions are queued again.
+    fn run_backwards(&mut self, count: Option<usize>) -> String {
+        let mut undone = 0;
+        let stop = loop {
+            if count == Some(undone) {
+                break Stop::Stepped;
+            }
+            if count.is_none() && undone > 0 && self.breakpoints.contains(&self.program.instruction_pointer()) {
+                break Stop::Breakpoint;
+            }
+            let input = match self.program.undo_log().unwrap().entries.back() {
+                Some(entry) => entry.input,
+                None => break Stop::HistoryStart,
+            };
+            self.program.step_back();
+            if let Some(input) = input {
+                self.inputs.push_front(input);
+            }
+            undone += 1;
+        };
+        let mut text = Vec::new();
+        match stop {
+            Stop::Breakpoint => text.push("breakpoint hit".to_string()),
+            Stop::HistoryStart => text.push("reached the start of the undo history".to_string()),
+            _ => (),
+        }
+        text.push(self.current_instruction());
+        return text.join("\n");
+    }
+
+    /// Executes the given number of instructions, or until a breakpoint is hit if no count is given.
+    fn run(&mut self, count: Option<usize>) -> String {
+        let mut text = Vec::new();
+        let mut executed = 0;
+        let stop = loop {
+            if count == Some(executed) {
+                break Stop::Stepped;
+            }
+            if count.is_none() && executed > 0 && self.breakpoints.contains(&self.program.instruction_pointer()) {
+                break Stop::Breakpoint;
+            }
+            let input = match self.program.next_opcode() {
+                Ok(Opcode::Terminate) => break Stop::Halted,
+                Ok(opcode) if opcode.reads_input() => match self.inputs.pop_front() {
+                    Some(input) => Some(input),
+                    None => break Stop::NeedsInput,
+                },
+                _ => None,
+            };
+            match self.program.step(input) {
+                Ok(Some(output)) => text.push(format!("output: {}", output)),
+                Ok(None) => (),
+                Err(error) => {
+                    if let Some(input) = input {
+                        self.inputs.push_front(input);
+                    }
+                    text.push(format!("error: {}", error));
+                    break Stop::Failed;
+                }
+            }
+            executed += 1;
+        };
+        match stop {
+            Stop::Breakpoint => text.push("breakpoint hit".to_string()),
+            Stop::NeedsInput => text.push("program needs input, use 'input <value>'".to_string()),
+            Stop::Halted => text.push("program halted".to_string()),
+            Stop::Stepped | Stop::Failed | Stop::HistoryStart => (),
+        }
+        text.push(self.current_instruction());
+        return text.join("\n");
+    }
+}
+
+fn main() {
+    let mut debugger = Debugger::new(Vec::new());
+    if let Some(file_name) = env::args().nth(1) {
+        println!("{}", debugger.execute(Command::Load(file_name)).unwrap());
+    }
+    let stdin = io::stdin();
+    loop {
+        print!("(intcode) ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_command(&line) {
+            Ok(command) => match debugger.execute(command) {
+                Some(text) => println!("{}", text),
+                None => break,
+            },
+            Err(error) => println!("{}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(parse_command("b 12"), Ok(Command::Break(12)));
+        assert_eq!(parse_command("step"), Ok(Command::Step(1)));
+        assert_eq!(parse_command("s 5"), Ok(Command::Step(5)));
+        assert_eq!(parse_command("m 4"), Ok(Command::Memory(4, 5)));
+        assert_eq!(parse_command("memory 4 20"), Ok(Command::Memory(4, 20)));
+        assert_eq!(parse_command("m 18446744073709551615"), Ok(Command::Memory(usize::MAX, usize::MAX)));
+        assert_eq!(parse_command("input 1 -2"), Ok(Command::Input(vec![1, -2])));
+        assert_eq!(parse_command("cfg out.dot"), Ok(Command::Cfg("out.dot".to_string())));
+        assert_eq!(parse_command("decompile out.c"), Ok(Command::Decompile("out.c".to_string())));
+        assert_eq!(parse_command("profile"), Ok(Command::Profile(10)));
+        assert_eq!(parse_command("sb 3"), Ok(Command::Back(3)));
+        assert!(parse_command("break x").is_err());
+        assert!(parse_command("jump 3").is_err());
+    }
+
+    #[test]
+    fn step_and_inspect() {
+        let mut debugger = Debugger::new(vec![109, 19, 204, -8, 99]);
+        assert_eq!(debugger.execute(Command::Instruction).unwrap(), "      0: 109 19                           ARB #19");
+        debugger.execute(Command::Step(1));
+        assert_eq!(
+            debugger.execute(Command::Registers).unwrap(),
+            "instruction pointer: 2, relative base: 19"
+        );
+        assert_eq!(
+            debugger.execute(Command::Memory(0, 10)).unwrap(),
+            "     0:      109       19      204       -8       99        0        0        0\n     8:        0        0"
+        );
+    }
+
+    #[test]
+    fn continue_until_breakpoint_and_input() {
+        // reads a value, adds one to it and outputs it
+        let mut debugger = Debugger::new(vec![3, 9, 1001, 9, 1, 9, 4, 9, 99, 0]);
+        debugger.execute(Command::Break(6));
+        let text = debugger.execute(Command::Continue).unwrap();
+        assert!(text.starts_with("program needs input"));
+        debugger.execute(Command::Input(vec![41]));
+        let text = debugger.execute(Command::Continue).unwrap();
+        assert!(text.starts_with("breakpoint hit\n*     6: 4 9"));
+        assert_eq!(debugger.program.read_memory(9), 42);
+        let text = debugger.execute(Command::Continue).unwrap();
+        assert!(text.starts_with("output: 42\nprogram halted"));
+        let profile = debugger.execute(Command::Profile(1)).unwrap();
+        assert!(profile.starts_with("instructions executed: 3\ncoverage: 8 of 10 words (80.0%)\n"));
+        let text = debugger.execute(Command::Reverse).unwrap();
+        assert!(text.starts_with("breakpoint hit\n*     6: 4 9"));
+        let text = debugger.execute(Command::Back(5)).unwrap();
+        assert!(text.starts_with("reached the start of the undo history\n      0: 3 9"));
+        assert_eq!(debugger.inputs, [41]);
+        debugger.execute(Command::Reset);
+        assert_eq!(debugger.program.instruction_pointer(), 0);
+        assert_eq!(debugger.program.read_memory(9), 0);
+    }
+
+    #[test]
+    fn save_and_restore_snapshot() {
+        let path = std::env::temp_dir().join(format!("intcode-debug-test-{}", std::process::id()));
+        let file_name = path.to_str().unwrap().to_string();
+        let mut debugger = Debugger::new(vec![109, 19, 204, -8, 99]);
+        debugger.execute(Command::Step(1));
+        debugger.execute(Command::Input(vec![4, 5]));
+        debugger.execute(Command::Save(file_name.clone()));
+        let mut debugger = Debugger::new(vec![99]);
+        debugger.execute(Command::Input(vec![6]));
+        let text = debugger.execute(Command::Restore(file_name)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(text.ends_with("     2: 204 -8                           OUT rb-8"));
+        assert_eq!(debugger.program.relative_base(), 19);
+        assert_eq!(debugger.inputs, [4, 5]);
+        debugger.execute(Command::Continue);
+        debugger.execute(Command::Input(vec![7]));
+        debugger.execute(Command::Reset);
+        assert_eq!(debugger.program.instruction_pointer(), 2);
+        assert_eq!(debugger.program.relative_base(), 19);
+        assert_eq!(debugger.inputs, [4, 5]);
+    }
+
+    #[test]
+    fn errors_stop_execution() {
+        let mut debugger = Debugger::new(vec![1101, 1, 1, 5, 42]);
+        let text = debugger.execute(Command::Continue).unwrap();
+        assert!(text.starts_with("error: #4 (opcode 42): invalid opcode 42"));
+        assert_eq!(debugger.program.instruction_pointer(), 4);
+    }
+}
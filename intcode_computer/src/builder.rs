@@ -0,0 +1,59 @@
+//! Configures a program before it starts, e.g. with custom opcodes.
+use crate::custom::{CustomAction, CustomInstruction, CustomOpcode, OpcodeDefinition};
+use crate::{parse_instruction, ErrorReason, Memory, OverflowPolicy, PagedMemory, Program};
+use std::sync::{Arc, Mutex};
+
+pub struct ProgramBuilder<M: Memory = PagedMemory> {
+    program: Program<M>,
+}
+
+impl ProgramBuilder {
+    pub fn new<'a>(code: impl IntoIterator<Item=&'a i64>) -> ProgramBuilder {
+        return ProgramBuilder { program: Program::init(code) };
+    }
+}
+
+impl<M: Memory> ProgramBuilder<M> {
+    pub fn with_memory(memory: M) -> ProgramBuilder<M> {
+        return ProgramBuilder { program: Program::with_memory(memory) };
+    }
+
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> ProgramBuilder<M> {
+        self.program.set_overflow_policy(policy);
+        return self;
+    }
+
+    /// Registers a custom opcode, executing an instruction calls the handler.
+    /// Clones of the program share the handler.
+    ///
+    /// Panics if the definition is invalid, or its number is already used by another opcode.
+    pub fn opcode(
+        mut self,
+        definition: OpcodeDefinition,
+        handler: impl FnMut(&mut CustomInstruction<M>) -> Result<CustomAction, ErrorReason> + Send + 'static,
+    ) -> ProgramBuilder<M> {
+        assert!((0..100).contains(&definition.number), "opcode {} doesn't fit into two digits", definition.number);
+        assert!(parse_instruction(definition.number).is_err(), "opcode {} is built in", definition.number);
+        assert!(
+            !self.program.custom_opcodes.contains_key(&definition.number),
+            "opcode {} is already registered",
+            definition.number
+        );
+        assert!(definition.parameter_count <= 3, "opcode {} has more than 3 parameters", definition.number);
+        if let Some(result_parameter) = definition.result_parameter {
+            assert!(
+                (1..=definition.parameter_count).contains(&result_parameter),
+                "opcode {} has no parameter {}",
+                definition.number,
+                result_parameter
+            );
+        }
+        let custom = CustomOpcode { definition, handler: Arc::new(Mutex::new(handler)) };
+        self.program.custom_opcodes.insert(definition.number, custom);
+        return self;
+    }
+
+    pub fn build(self) -> Program<M> {
+        return self.program;
+    }
+}
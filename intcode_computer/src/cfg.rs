@@ -0,0 +1,310 @@
+//! Static control-flow analysis: splits code into basic blocks and connects them into a graph.
+//!
+//! Code is only decoded where it's reachable from the entry point, following immediate jump targets,
+//! so data between functions isn't mistaken for instructions. Jumps to targets that are only known at runtime,
+//! e.g. returns through an address on the stack, can't be followed and are marked as indirect instead.
+//! Self-modifying code isn't detected, the graph describes the code as it was passed in.
+use crate::disassembler::{decode, Decoded, DisassembledLine};
+use crate::{Opcode, ParameterMode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+
+/// How control leaves a basic block.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Exit {
+    /// Continues with the block at the given address, as that address is the target of some jump.
+    FallThrough(usize),
+    /// Unconditional jump, including conditional jumps with an immediate condition that always jump.
+    Jump(usize),
+    Branch { taken: usize, not_taken: usize },
+    /// A jump to a target that isn't known statically or isn't a valid address.
+    /// `not_taken` is the next block if the jump is conditional.
+    IndirectJump { not_taken: Option<usize> },
+    Halt,
+    /// The block runs into words that aren't a valid instruction, or past the end of the code.
+    Invalid,
+}
+
+impl Exit {
+    pub fn successors(&self) -> Vec<usize> {
+        return match *self {
+            Exit::FallThrough(next) | Exit::Jump(next) => vec![next],
+            Exit::Branch { taken, not_taken } => vec![taken, not_taken],
+            Exit::IndirectJump { not_taken } => not_taken.into_iter().collect(),
+            Exit::Halt | Exit::Invalid => Vec::new(),
+        };
+    }
+}
+
+/// A sequence of instructions that is only entered at its first instruction and only left after its last one.
+#[derive(Debug, PartialEq, Clone)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub instructions: Vec<DisassembledLine>,
+    pub exit: Exit,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ControlFlowGraph {
+    pub entry: usize,
+    /// Blocks by their start address.
+    pub blocks: BTreeMap<usize, BasicBlock>,
+}
+
+/// Where an instruction continues: the statically known targets, and whether it may jump to an unknown one.
+struct Flow {
+    targets: Vec<usize>,
+    next: Option<usize>,
+    indirect: bool,
+}
+
+/// Determines the control flow of a single decoded line.
+fn flow(line: &DisassembledLine) -> Flow {
+    let next = line.address + line.words.len();
+    let (opcode, parameters) = match &line.decoded {
+        Decoded::Instruction { opcode, parameters } => (*opcode, parameters),
+        Decoded::Data(_) => return Flow { targets: Vec::new(), next: None, indirect: false },
+    };
+    let jumps_if_zero = match opcode {
+        Opcode::JumpIfTrue => false,
+        Opcode::JumpIfFalse => true,
+        Opcode::Terminate => return Flow { targets: Vec::new(), next: None, indirect: false },
+        _ => return Flow { targets: Vec::new(), next: Some(next), indirect: false },
+    };
+    let (condition, target) = (parameters[0], parameters[1]);
+    let (may_jump, may_continue) = match condition.mode {
+        ParameterMode::Immediate => ((condition.value == 0) == jumps_if_zero, (condition.value == 0) != jumps_if_zero),
+        _ => (true, true),
+    };
+    let next = if may_continue { Some(next) } else { None };
+    if !may_jump {
+        return Flow { targets: Vec::new(), next, indirect: false };
+    }
+    return match (target.mode, usize::try_from(target.value)) {
+        (ParameterMode::Immediate, Ok(target)) => Flow { targets: vec![target], next, indirect: false },
+        _ => Flow { targets: Vec::new(), next, indirect: true },
+    };
+}
+
+fn decode_at(code: &[i64], address: usize) -> DisassembledLine {
+    return decode(address, code.get(address..).unwrap_or(&[]));
+}
+
+/// Builds the control-flow graph of the code reachable from address 0.
+pub fn extract(code: &[i64]) -> ControlFlowGraph {
+    return extract_from(code, 0);
+}
+
+/// Builds the control-flow graph of the code reachable from the given entry address.
+pub fn extract_from(code: &[i64], entry: usize) -> ControlFlowGraph {
+    // find all reachable instructions, and the addresses where blocks have to start
+    let mut lines = BTreeMap::new();
+    let mut leaders = BTreeSet::new();
+    leaders.insert(entry);
+    let mut pending = vec![entry];
+    while let Some(address) = pending.pop() {
+        if lines.contains_key(&address) {
+            continue;
+        }
+        let line = decode_at(code, address);
+        let flow = flow(&line);
+        let is_jump = !flow.targets.is_empty() || flow.indirect;
+        leaders.extend(flow.targets.iter().cloned());
+        pending.extend(flow.targets.iter().cloned());
+        if let Some(next) = flow.next {
+            if is_jump {
+                leaders.insert(next);
+            }
+            pending.push(next);
+        }
+        lines.insert(address, line);
+    }
+
+    let mut blocks = BTreeMap::new();
+    for start in leaders.iter().cloned() {
+        let mut instructions = Vec::new();
+        let mut address = start;
+        let exit = loop {
+            let line = lines[&address].clone();
+            let is_data = matches!(line.decoded, Decoded::Data(_));
+            let flow = flow(&line);
+            instructions.push(line);
+            if is_data {
+                break Exit::Invalid;
+            }
+            break match (flow.targets.first(), flow.next, flow.indirect) {
+                (Some(target), Some(next), _) => Exit::Branch { taken: *target, not_taken: next },
+                (Some(target), None, _) => Exit::Jump(*target),
+                (None, next, true) => Exit::IndirectJump { not_taken: next },
+                (None, None, false) => Exit::Halt,
+                (None, Some(next), false) if leaders.contains(&next) => Exit::FallThrough(next),
+                (None, Some(next), false) => {
+                    address = next;
+                    continue;
+                }
+            };
+        };
+        blocks.insert(start, BasicBlock { start, instructions, exit });
+    }
+    return ControlFlowGraph { entry, blocks };
+}
+
+impl ControlFlowGraph {
+    /// Blocks ending in a jump whose target couldn't be resolved.
+    pub fn indirect_jumps(&self) -> Vec<&BasicBlock> {
+        return self
+            .blocks
+            .values()
+            .filter(|block| matches!(block.exit, Exit::IndirectJump { .. }))
+            .collect();
+    }
+
+    /// Renders the graph in Graphviz' DOT language, e.g. for `dot -Tsvg`.
+    /// Indirect jumps point to a shared "unresolved" node, halting blocks have a double border.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for line in block.instructions.iter() {
+                label += &format!("{}: {}\\l", line.address, line.decoded);
+            }
+            let style = match block.exit {
+                Exit::Halt => ", peripheries=2",
+                Exit::Invalid => ", style=dashed",
+                _ => "",
+            };
+            dot += &format!("    b{} [label=\"{}\"{}];\n", block.start, label, style);
+        }
+        dot += &format!("    entry [shape=point];\n    entry -> b{};\n", self.entry);
+        let mut has_indirect_jumps = false;
+        for block in self.blocks.values() {
+            let edges = match block.exit {
+                Exit::FallThrough(next) | Exit::Jump(next) => vec![(next, "")],
+                Exit::Branch { taken, not_taken } => vec![(taken, "taken"), (not_taken, "not taken")],
+                Exit::IndirectJump { not_taken } => {
+                    has_indirect_jumps = true;
+                    dot += &format!("    b{} -> unresolved [style=dashed];\n", block.start);
+                    not_taken.map(|next| (next, "not taken")).into_iter().collect()
+                }
+                Exit::Halt | Exit::Invalid => Vec::new(),
+            };
+            for (target, label) in edges {
+                match label {
+                    "" => dot += &format!("    b{} -> b{};\n", block.start, target),
+                    _ => dot += &format!("    b{} -> b{} [label=\"{}\"];\n", block.start, target, label),
+                }
+            }
+        }
+        if has_indirect_jumps {
+            dot += "    unresolved [label=\"unresolved jump\", shape=ellipse, style=dashed];\n";
+        }
+        dot += "}\n";
+        return dot;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn loop_with_branch() {
+        // counts [x] down to zero
+        let source = "
+            loop:   ADD [x], #-1, [x]
+                    JNZ [x], #loop
+                    HLT
+            x:      .data 3
+        ";
+        let code = assemble(source).unwrap();
+        let graph = extract(&code);
+        assert_eq!(graph.blocks.len(), 2);
+        assert_eq!(graph.blocks[&0].instructions.len(), 2);
+        assert_eq!(graph.blocks[&0].exit, Exit::Branch { taken: 0, not_taken: 7 });
+        assert_eq!(graph.blocks[&7].exit, Exit::Halt);
+        assert!(graph.indirect_jumps().is_empty());
+    }
+
+    #[test]
+    fn data_after_unconditional_jump_is_skipped() {
+        let source = "
+                    JNZ #1, #code
+                    .data 77, 88
+            code:   JZ #0, #end
+                    .data 66
+            end:    OUT #1
+                    HLT
+        ";
+        let code = assemble(source).unwrap();
+        let graph = extract(&code);
+        let starts: Vec<usize> = graph.blocks.keys().cloned().collect();
+        assert_eq!(starts, [0, 5, 9]);
+        assert_eq!(graph.blocks[&0].exit, Exit::Jump(5));
+        assert_eq!(graph.blocks[&5].exit, Exit::Jump(9));
+        assert_eq!(graph.blocks[&9].instructions.len(), 2);
+    }
+
+    #[test]
+    fn fall_through_into_jump_target() {
+        let source = "
+                    IN [x]
+            again:  OUT [x]
+                    JZ [x], #again
+                    HLT
+            x:      .data 0
+        ";
+        let graph = extract(&assemble(source).unwrap());
+        assert_eq!(graph.blocks[&0].exit, Exit::FallThrough(2));
+        assert_eq!(graph.blocks[&2].exit, Exit::Branch { taken: 2, not_taken: 7 });
+    }
+
+    #[test]
+    fn indirect_jumps_are_marked() {
+        // calls a function which returns through the address stored at [ret]
+        let source = "
+                    ADD #back, #0, [ret]
+                    JNZ #1, #func
+            back:   HLT
+            func:   OUT #1
+                    JNZ #1, [ret]
+            ret:    .data 0
+        ";
+        let code = assemble(source).unwrap();
+        let graph = extract(&code);
+        assert_eq!(graph.indirect_jumps().len(), 1);
+        assert_eq!(graph.blocks[&8].exit, Exit::IndirectJump { not_taken: None });
+        // the return address isn't known statically, so the code after the call isn't reachable
+        assert!(!graph.blocks.contains_key(&7));
+        assert_eq!(extract_from(&code, 7).blocks[&7].exit, Exit::Halt);
+    }
+
+    #[test]
+    fn running_into_data_is_invalid() {
+        let graph = extract(&[1, 0, 0, 0, 42]);
+        assert_eq!(graph.blocks[&0].exit, Exit::Invalid);
+        assert_eq!(graph.blocks[&0].instructions[1].decoded, Decoded::Data(42));
+        assert_eq!(extract(&[1105, 1, 9]).blocks[&9].exit, Exit::Invalid);
+    }
+
+    #[test]
+    fn dot_export() {
+        let graph = extract(&[3, 7, 1005, 7, 7, 99, 99, 0]);
+        assert_eq!(
+            graph.to_dot(),
+            "digraph cfg {
+    node [shape=box, fontname=\"monospace\"];
+    b0 [label=\"0: IN [7]\\l2: JNZ [7], #7\\l\"];
+    b5 [label=\"5: HLT\\l\", peripheries=2];
+    b7 [label=\"7: .data 0\\l\", style=dashed];
+    entry [shape=point];
+    entry -> b0;
+    b0 -> b7 [label=\"taken\"];
+    b0 -> b5 [label=\"not taken\"];
+}
+"
+        );
+        let graph = extract(&[1106, 0, 3, 105, 1, 7, 99]);
+        assert!(graph.to_dot().contains("    b3 -> unresolved [style=dashed];\n"));
+    }
+}
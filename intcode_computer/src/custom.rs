@@ -0,0 +1,236 @@
+//! Opcodes defined outside of this crate, registered with `ProgramBuilder::opcode`.
+//!
+//! Custom opcodes use the same instruction format as the built-in ones, with up to three parameters.
+//! Only their parameters are seen by self-modification detection, watchpoints and the undo log,
+//! other memory a handler accesses through `CustomInstruction::read` and `CustomInstruction::write` isn't.
+use crate::decode_cache::Instruction;
+use crate::{parse_parameter_mode, ErrorReason, Memory, Opcode, PagedMemory, ParameterMode, Program};
+use std::sync::{Arc, Mutex};
+
+/// Describes the instruction format of a custom opcode.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct OpcodeDefinition {
+    /// The last two digits of the instruction, must not be used by a built-in opcode.
+    pub number: i64,
+    pub name: &'static str,
+    /// At most 3.
+    pub parameter_count: usize,
+    /// The id of the parameter that describes the address the instruction writes to, if any.
+    pub result_parameter: Option<usize>,
+    /// Whether the instruction consumes an input, which is provided like for `IN`.
+    pub reads_input: bool,
+}
+
+/// What to do after a custom instruction was executed.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CustomAction {
+    /// Continue with the next instruction.
+    Continue,
+    Jump(usize),
+    /// Continue with the next instruction, but return `RunState::Yielded` from `Program::resume`.
+    Yield,
+}
+
+pub(crate) type CustomHandler<M> = dyn FnMut(&mut CustomInstruction<M>) -> Result<CustomAction, ErrorReason> + Send;
+
+#[derive(Clone)]
+pub(crate) struct CustomOpcode<M: Memory> {
+    pub(crate) definition: OpcodeDefinition,
+    pub(crate) handler: Arc<Mutex<CustomHandler<M>>>,
+}
+
+/// The program as seen by a custom opcode's handler.
+/// If the handler fails, it should do so before writing to memory, as the program is expected to be left unchanged.
+pub struct CustomInstruction<'a, M: Memory = PagedMemory> {
+    program: &'a mut Program<M>,
+    parameter_count: usize,
+    modes: [ParameterMode; 3],
+    input: Option<i64>,
+    output: Option<i64>,
+}
+
+impl<'a, M: Memory> CustomInstruction<'a, M> {
+    pub fn instruction_pointer(&self) -> usize {
+        return self.program.instruction_pointer;
+    }
+
+    pub fn relative_base(&self) -> usize {
+        return self.program.relative_base;
+    }
+
+    /// The mode of a parameter, fails if the opcode's definition has no such parameter.
+    fn mode(&self, parameter_id: usize) -> Result<ParameterMode, ErrorReason> {
+        return match parameter_id {
+            1..=3 if parameter_id <= self.parameter_count => Ok(self.modes[parameter_id - 1]),
+            _ => Err(ErrorReason::UnknownParameter { parameter_id }),
+        };
+    }
+
+    /// Resolves a parameter into the value it describes, depending on its parameter mode.
+    pub fn value(&self, parameter_id: usize) -> Result<i64, ErrorReason> {
+        return self.program.resolve_parameter_to_value(parameter_id, self.mode(parameter_id)?);
+    }
+
+    /// Resolves a parameter into the address it describes, fails if it's in immediate mode.
+    pub fn address(&self, parameter_id: usize) -> Result<usize, ErrorReason> {
+        return self.program.resolve_parameter_to_result_address(parameter_id, self.mode(parameter_id)?);
+    }
+
+    pub fn read(&self, address: usize) -> i64 {
+        return self.program.read_memory(address);
+    }
+
+    pub fn write(&mut self, address: usize, value: i64) {
+        self.program.set_memory(address, value);
+    }
+
+    /// Takes the input, fails if none was given or it was already taken.
+    pub fn input(&mut self) -> Result<i64, ErrorReason> {
+        return self.input.take().ok_or(ErrorReason::MissingInput);
+    }
+
+    /// Sets the instruction's output, an instruction has at most one.
+    pub fn output(&mut self, value: i64) {
+        self.output = Some(value);
+    }
+}
+
+impl<M: Memory> Program<M> {
+    /// Decodes an instruction word of a registered custom opcode, None if there is no such opcode.
+    pub(crate) fn decode_custom(&self, word: i64) -> Option<Result<Instruction, ErrorReason>> {
+        let custom = self.custom_opcodes.get(&(word % 100))?;
+        let modes = (|| {
+            return Ok((
+                parse_parameter_mode(word, 1)?,
+                parse_parameter_mode(word, 2)?,
+                parse_parameter_mode(word, 3)?,
+            ));
+        })();
+        return Some(modes.map(|(pm1, pm2, pm3)| (Opcode::Custom(custom.definition), pm1, pm2, pm3)));
+    }
+
+    pub(crate) fn execute_custom(
+        &mut self,
+        definition: OpcodeDefinition,
+        modes: [ParameterMode; 3],
+        input: Option<i64>,
+    ) -> Result<Option<i64>, ErrorReason> {
+        let handler = self.custom_opcodes[&definition.number].handler.clone();
+        let mut instruction =
+            CustomInstruction { program: self, parameter_count: definition.parameter_count, modes, input, output: None };
+        let action = (handler.lock().expect("custom opcode handler panicked"))(&mut instruction)?;
+        let output = instruction.output;
+        match action {
+            CustomAction::Continue => self.instruction_pointer += definition.parameter_count + 1,
+            CustomAction::Jump(target) => self.instruction_pointer = target,
+            CustomAction::Yield => {
+                self.instruction_pointer += definition.parameter_count + 1;
+                self.yielded = true;
+            }
+        }
+        return Ok(output);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ProgramBuilder, RunState};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // MAX a, b, c: c = max(a, b)
+    const MAX: OpcodeDefinition = OpcodeDefinition {
+        number: 42,
+        name: "MAX",
+        parameter_count: 3,
+        result_parameter: Some(3),
+        reads_input: false,
+    };
+
+    fn max(instruction: &mut CustomInstruction) -> Result<CustomAction, ErrorReason> {
+        let value = instruction.value(1)?.max(instruction.value(2)?);
+        let address = instruction.address(3)?;
+        instruction.write(address, value);
+        return Ok(CustomAction::Continue);
+    }
+
+    #[test]
+    fn custom_opcode_with_parameter_modes() {
+        let code = [109, 10, 1042, 7, 12, 0, 22042, 11, 1, -10, 99, 13, 0];
+        let mut program = ProgramBuilder::new(&code).opcode(MAX, max).build();
+        program.step(None).unwrap();
+        assert_eq!(program.next_opcode(), Ok(Opcode::Custom(MAX)));
+        program.step(None).unwrap();
+        assert_eq!(program.read_memory(0), 12);
+        program.run(Vec::new()).unwrap();
+        assert_eq!(program.read_memory(0), 13);
+        assert_eq!(Program::init(&code).run(Vec::new()).err().unwrap().reason, ErrorReason::InvalidOpcode(1042));
+    }
+
+    #[test]
+    fn custom_input_output_and_jumps() {
+        // 50: outputs twice its input, 51: jumps to its parameter
+        let double = OpcodeDefinition { number: 50, name: "DBL", parameter_count: 0, result_parameter: None, reads_input: true };
+        let jump = OpcodeDefinition { number: 51, name: "JMP", parameter_count: 1, result_parameter: None, reads_input: false };
+        let mut program = ProgramBuilder::new(&[50, 151, 0])
+            .opcode(double, |instruction| {
+                let input = instruction.input()?;
+                instruction.output(2 * input);
+                return Ok(CustomAction::Continue);
+            })
+            .opcode(jump, |instruction| Ok(CustomAction::Jump(instruction.value(1)? as usize)))
+            .build();
+        program.provide_input(4);
+        program.provide_input(5);
+        assert_eq!(program.resume(), Ok(RunState::Output(8)));
+        assert_eq!(program.resume(), Ok(RunState::Output(10)));
+        assert_eq!(program.resume(), Ok(RunState::NeedsInput));
+    }
+
+    #[test]
+    fn yield_and_shared_state() {
+        let yields = Arc::new(AtomicUsize::new(0));
+        let counter = yields.clone();
+        let yield_opcode = OpcodeDefinition { number: 60, name: "YLD", parameter_count: 0, result_parameter: None, reads_input: false };
+        let mut program = ProgramBuilder::new(&[60, 104, 7, 60, 99])
+            .opcode(yield_opcode, move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                return Ok(CustomAction::Yield);
+            })
+            .build();
+        assert_eq!(program.resume(), Ok(RunState::Yielded));
+        assert_eq!(program.resume(), Ok(RunState::Output(7)));
+        assert_eq!(program.clone().run(Vec::new()).unwrap(), []);
+        assert_eq!(program.resume(), Ok(RunState::Yielded));
+        assert_eq!(program.resume(), Ok(RunState::Halted));
+        assert_eq!(yields.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn unknown_parameters_fail() {
+        let mut program = ProgramBuilder::new(&[1042, 7, 12, 0, 99])
+            .opcode(MAX, |instruction| {
+                for parameter_id in [0, 4].iter() {
+                    assert_eq!(
+                        instruction.value(*parameter_id),
+                        Err(ErrorReason::UnknownParameter { parameter_id: *parameter_id })
+                    );
+                }
+                return max(instruction);
+            })
+            .build();
+        program.run(Vec::new()).unwrap();
+        assert_eq!(program.read_memory(0), 12);
+        let one_parameter = OpcodeDefinition { number: 43, parameter_count: 1, result_parameter: None, ..MAX };
+        let mut program = ProgramBuilder::new(&[43, 5, 6, 99]).opcode(one_parameter, max).build();
+        let error = program.run(Vec::new()).err().unwrap();
+        assert_eq!(error.instruction_pointer, 0);
+        assert_eq!(error.reason, ErrorReason::UnknownParameter { parameter_id: 2 });
+    }
+
+    #[test]
+    #[should_panic(expected = "opcode 7 is built in")]
+    fn built_in_opcodes_cant_be_replaced() {
+        ProgramBuilder::new(&[99]).opcode(OpcodeDefinition { number: 7, ..MAX }, max);
+    }
+}
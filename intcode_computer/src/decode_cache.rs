@@ -0,0 +1,68 @@
+use crate::{Opcode, ParameterMode};
+
+pub(crate) type Instruction = (Opcode, ParameterMode, ParameterMode, ParameterMode);
+
+/// Instructions aren't cached beyond this address, so a program jumping far out doesn't allocate a huge cache.
+const MAX_CACHED_ADDRESS: usize = 1 << 16;
+
+/// Decoded instructions by the address of their opcode.
+/// Parameters aren't cached, so an entry only has to be invalidated when its opcode is overwritten.
+#[derive(Clone, Default)]
+pub(crate) struct DecodeCache {
+    instructions: Vec<Option<Instruction>>,
+}
+
+impl DecodeCache {
+    pub(crate) fn get(&self, address: usize) -> Option<Instruction> {
+        return *self.instructions.get(address)?;
+    }
+
+    pub(crate) fn insert(&mut self, address: usize, instruction: Instruction) {
+        if address >= MAX_CACHED_ADDRESS {
+            return;
+        }
+        if address >= self.instructions.len() {
+            self.instructions.resize(address + 1, None);
+        }
+        self.instructions[address] = Some(instruction);
+    }
+
+    /// Has to be called on every write to memory.
+    pub(crate) fn invalidate(&mut self, address: usize) {
+        if let Some(instruction) = self.instructions.get_mut(address) {
+            *instruction = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Program;
+
+    #[test]
+    fn cache_is_invalidated() {
+        let instruction = (Opcode::Add, ParameterMode::Position, ParameterMode::Immediate, ParameterMode::Relative);
+        let mut cache = DecodeCache::default();
+        assert_eq!(cache.get(3), None);
+        cache.insert(3, instruction);
+        assert_eq!(cache.get(3), Some(instruction));
+        cache.invalidate(4);
+        assert_eq!(cache.get(3), Some(instruction));
+        cache.invalidate(3);
+        assert_eq!(cache.get(3), None);
+        cache.insert(MAX_CACHED_ADDRESS, instruction);
+        assert_eq!(cache.get(MAX_CACHED_ADDRESS), None);
+    }
+
+    #[test]
+    fn executed_instructions_are_cached() {
+        let mut program = Program::init(&[1101, 2, 3, 0, 99]);
+        program.step(None).unwrap();
+        // the instruction overwrote itself
+        assert_eq!(program.decode_cache.get(0), None);
+        assert_eq!(program.decode_cache.get(4), None);
+        program.step(None).unwrap();
+        assert_eq!(program.decode_cache.get(4).unwrap().0, Opcode::Terminate);
+    }
+}
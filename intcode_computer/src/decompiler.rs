@@ -0,0 +1,836 @@
+//! Decompiles code into C-like pseudocode, to make sense of compiled programs.
+//!
+//! Functions are recognized by the calling convention of compiled Intcode: the caller stores the return address
+//! through the relative base and jumps to the function, which moves the relative base past its frame
+//! and finally jumps back through the stored address. Inside a function, stack slots are named after their offset
+//! from the return address: `arg1`, `arg2`... for arguments passed by callers, `local3`... for everything else.
+//! Memory accessed in position mode is named `var_<address>`, and so is the stack of `main`, which starts at address 0.
+//! Parameters that are overwritten at runtime, e.g. to index arrays, are shown as pointers: `mem[var_<address>]`.
+//!
+//! `if`/`else`, `while` and `do`/`while` are recovered from the jumps, anything else falls back to `goto`.
+use crate::disassembler::{decode, Decoded, DisassembledLine, Parameter};
+use crate::{Opcode, ParameterMode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+
+const MAIN: usize = 0;
+const INDENT: &str = "    ";
+
+fn decode_at(code: &[i64], address: usize) -> DisassembledLine {
+    return decode(address, code.get(address..).unwrap_or(&[]));
+}
+
+fn instruction(line: &DisassembledLine) -> Option<(Opcode, &[Parameter])> {
+    return match &line.decoded {
+        Decoded::Instruction { opcode, parameters } => Some((*opcode, parameters)),
+        Decoded::Data(_) => None,
+    };
+}
+
+/// Whether a jump with the given immediate condition is always taken, None if the condition isn't immediate.
+fn is_taken(opcode: Opcode, condition: Parameter) -> Option<bool> {
+    return match condition.mode {
+        ParameterMode::Immediate => Some((condition.value != 0) == (opcode == Opcode::JumpIfTrue)),
+        _ => None,
+    };
+}
+
+/// A call: an unconditional jump to an immediate target, right after storing the return address through the relative base.
+struct Call {
+    callee: usize,
+    /// Address of the instruction storing the return address.
+    store: usize,
+    /// Relative base offset the return address is stored at.
+    offset: i64,
+}
+
+fn call_at(code: &[i64], line: &DisassembledLine) -> Option<Call> {
+    let (opcode, parameters) = instruction(line)?;
+    if !matches!(opcode, Opcode::JumpIfTrue | Opcode::JumpIfFalse) || is_taken(opcode, parameters[0]) != Some(true) {
+        return None;
+    }
+    let callee = match parameters[1].mode {
+        ParameterMode::Immediate => usize::try_from(parameters[1].value).ok()?,
+        _ => return None,
+    };
+    let store = decode_at(code, line.address.checked_sub(4)?);
+    let (store_opcode, store_parameters) = instruction(&store)?;
+    let immediates = (store_parameters.len() == 3)
+        && store_parameters[..2].iter().all(|parameter| parameter.mode == ParameterMode::Immediate)
+        && store_parameters[2].mode == ParameterMode::Relative;
+    if !immediates || store.words.len() != 4 {
+        return None;
+    }
+    let (first, second) = (store_parameters[0].value, store_parameters[1].value);
+    let return_address = match store_opcode {
+        Opcode::Add if first == 0 => second,
+        Opcode::Add if second == 0 => first,
+        Opcode::Mul if first == 1 => second,
+        Opcode::Mul if second == 1 => first,
+        _ => return None,
+    };
+    if return_address != (line.address + 3) as i64 {
+        return None;
+    }
+    return Some(Call { callee, store: store.address, offset: store_parameters[2].value });
+}
+
+struct Function {
+    entry: usize,
+    /// Reachable instructions, with the relative base as offset from its value at the entry, if it's known.
+    lines: BTreeMap<usize, (DisassembledLine, Option<i64>)>,
+    /// Whether the relative base is known everywhere, otherwise stack slots can't be named.
+    tracked: bool,
+    /// Offset of the return address from the relative base at the entry, known from the first return.
+    return_slot: Option<i64>,
+    /// Addresses where basic blocks start.
+    leaders: BTreeSet<usize>,
+    arguments: usize,
+}
+
+impl Function {
+    fn scan(code: &[i64], entry: usize) -> Function {
+        let mut function = Function {
+            entry,
+            lines: BTreeMap::new(),
+            tracked: true,
+            return_slot: None,
+            leaders: BTreeSet::new(),
+            arguments: 0,
+        };
+        function.leaders.insert(entry);
+        let mut pending = vec![(entry, Some(0))];
+        while let Some((address, base)) = pending.pop() {
+            if let Some((_, known_base)) = function.lines.get(&address) {
+                function.tracked &= *known_base == base;
+                continue;
+            }
+            let line = decode_at(code, address);
+            let next = address + line.words.len();
+            function.lines.insert(address, (line.clone(), base));
+            let (opcode, parameters) = match instruction(&line) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+            match opcode {
+                Opcode::Terminate => (),
+                Opcode::RelativeBaseOffset => {
+                    let base = match parameters[0].mode {
+                        ParameterMode::Immediate => base.and_then(|base| base.checked_add(parameters[0].value)),
+                        _ => None,
+                    };
+                    pending.push((next, base));
+                }
+                Opcode::JumpIfTrue | Opcode::JumpIfFalse if call_at(code, &line).is_none() => {
+                    let taken = is_taken(opcode, parameters[0]);
+                    if taken != Some(false) {
+                        match (parameters[1].mode, usize::try_from(parameters[1].value)) {
+                            (ParameterMode::Immediate, Ok(target)) => {
+                                function.leaders.insert(target);
+                                pending.push((target, base));
+                            }
+                            (ParameterMode::Relative, _) if function.return_slot.is_none() => {
+                                function.return_slot = base.and_then(|base| base.checked_add(parameters[1].value));
+                            }
+                            _ => (),
+                        }
+                    }
+                    if taken != Some(true) {
+                        function.leaders.insert(next);
+                        pending.push((next, base));
+                    }
+                }
+                _ => pending.push((next, base)),
+            }
+        }
+        function.tracked &= function.lines.values().all(|(_, base)| base.is_some());
+        return function;
+    }
+}
+
+/// The condition of a conditional jump, e.g. `!var_381`.
+#[derive(Clone)]
+struct Condition {
+    expression: String,
+    negated: bool,
+}
+
+impl Condition {
+    fn negate(&self) -> Condition {
+        return Condition { expression: self.expression.clone(), negated: !self.negated };
+    }
+
+    fn render(&self) -> String {
+        return if self.negated { format!("!{}", self.expression) } else { self.expression.clone() };
+    }
+}
+
+enum Terminator {
+    Next(usize),
+    /// Jumps to `target` if the condition holds.
+    Branch { condition: Condition, target: usize, next: usize },
+    Return,
+    Halt,
+    /// Control can't be followed statically, described by the statement.
+    Stop(String),
+}
+
+struct Block {
+    end: usize,
+    statements: Vec<String>,
+    terminator: Terminator,
+}
+
+/// Where the statements currently emitted are nested.
+#[derive(Clone, Copy, Default)]
+struct Context {
+    /// Where the enclosing `if` branch ends.
+    stop: Option<usize>,
+    loop_header: Option<usize>,
+    loop_exit: Option<usize>,
+    /// The last block of a `do`/`while` loop, whose jump becomes the loop condition.
+    latch: Option<usize>,
+}
+
+impl Context {
+    /// Whether the address isn't past the end of the enclosing `if` branch.
+    fn before_stop(&self, address: usize) -> bool {
+        return match self.stop {
+            Some(stop) => address <= stop,
+            None => true,
+        };
+    }
+}
+
+/// Turns the blocks of a function into nested statements.
+struct Structurer<'a> {
+    blocks: &'a BTreeMap<usize, Block>,
+    /// Blocks to print a label for.
+    labels: BTreeSet<usize>,
+    jumped_to: BTreeSet<usize>,
+    visited: BTreeSet<usize>,
+    text: String,
+}
+
+impl<'a> Structurer<'a> {
+    fn line(&mut self, indent: usize, text: &str) {
+        self.text += &format!("{}{}\n", INDENT.repeat(indent), text);
+    }
+
+    fn goto(&mut self, indent: usize, target: usize) {
+        self.jumped_to.insert(target);
+        self.line(indent, &format!("goto label_{};", target));
+    }
+
+    /// The last block jumping back to the given one, if it's a loop header.
+    /// Loops are nested into the enclosing loop or branch, later blocks are ignored.
+    fn latch(&self, header: usize, context: Context) -> Option<usize> {
+        let end = context.stop.into_iter().chain(context.loop_exit).min().unwrap_or(usize::MAX);
+        return self
+            .blocks
+            .range(header..end.max(header))
+            .filter(|(_, block)| match &block.terminator {
+                Terminator::Next(target) => *target == header,
+                Terminator::Branch { target, next, .. } => *target == header || *next == header,
+                _ => false,
+            })
+            .map(|(start, _)| *start)
+            .next_back();
+    }
+
+    /// Emits the statements starting at the given block, following the control flow until it leaves the context.
+    fn sequence(&mut self, mut address: usize, context: Context, indent: usize, mut entering_loop: bool) {
+        loop {
+            if Some(address) == context.stop {
+                return;
+            }
+            if !entering_loop && Some(address) == context.loop_header {
+                return self.line(indent, "continue;");
+            }
+            if Some(address) == context.loop_exit {
+                return self.line(indent, "break;");
+            }
+            if self.visited.contains(&address) || !self.blocks.contains_key(&address) {
+                return self.goto(indent, address);
+            }
+            if !entering_loop {
+                if let Some(latch) = self.latch(address, context) {
+                    match self.emit_loop(address, latch, indent) {
+                        Some(exit) => {
+                            address = exit;
+                            continue;
+                        }
+                        None => return,
+                    }
+                }
+            }
+            entering_loop = false;
+            self.visited.insert(address);
+            if self.labels.contains(&address) {
+                self.line(indent, &format!("label_{}:", address));
+            }
+            let block = &self.blocks[&address];
+            for statement in block.statements.iter() {
+                self.line(indent, statement);
+            }
+            if Some(address) == context.latch {
+                // within a branch, the loop condition is only reached by continuing
+                if context.stop.is_some() {
+                    self.line(indent, "continue;");
+                }
+                return;
+            }
+            match &block.terminator {
+                Terminator::Next(target) => address = *target,
+                Terminator::Return => return self.line(indent, "return;"),
+                Terminator::Halt => return self.line(indent, "halt();"),
+                Terminator::Stop(statement) => return self.line(indent, statement),
+                Terminator::Branch { condition, target, next } => {
+                    let (condition, target, next) = (condition.clone(), *target, *next);
+                    if Some(target) == context.loop_exit {
+                        self.line(indent, &format!("if ({}) break;", condition.render()));
+                    } else if Some(target) == context.loop_header {
+                        self.line(indent, &format!("if ({}) continue;", condition.render()));
+                    } else if target > address && (context.before_stop(target) || !context.before_stop(address)) {
+                        let join = self.join(target, next, context);
+                        self.line(indent, &format!("if ({}) {{", condition.negate().render()));
+                        self.sequence(next, Context { stop: Some(join), ..context }, indent + 1, false);
+                        if join != target {
+                            self.line(indent, "} else {");
+                            self.sequence(target, Context { stop: Some(join), ..context }, indent + 1, false);
+                        }
+                        self.line(indent, "}");
+                        address = join;
+                        continue;
+                    } else {
+                        self.jumped_to.insert(target);
+                        self.line(indent, &format!("if ({}) goto label_{};", condition.render(), target));
+                    }
+                    address = next;
+                }
+            }
+        }
+    }
+
+    /// Where both branches of an `if` meet: after the `else` branch if the `then` branch ends by jumping over it.
+    fn join(&self, target: usize, next: usize, context: Context) -> usize {
+        let last = self.blocks.range(next..target).map(|(_, block)| block).next_back();
+        return match last.map(|block| (block.end, &block.terminator)) {
+            Some((end, Terminator::Next(join)))
+                if end == target
+                    && *join > target
+                    && Some(*join) != context.loop_exit
+                    && context.before_stop(*join) =>
+            {
+                *join
+            }
+            _ => target,
+        };
+    }
+
+    /// Emits a loop, returns the address following it, if any.
+    fn emit_loop(&mut self, header: usize, latch: usize, indent: usize) -> Option<usize> {
+        let blocks = self.blocks;
+        let exit = blocks[&latch].end;
+        let inner = Context { stop: None, loop_header: Some(header), loop_exit: Some(exit), latch: None };
+        let header_block = &blocks[&header];
+        let while_loop = match (&header_block.terminator, &blocks[&latch].terminator) {
+            (Terminator::Branch { condition, target, next }, Terminator::Next(back))
+                if header_block.statements.is_empty() && header != latch && *back == header =>
+            {
+                if *target == exit {
+                    Some((condition.negate(), *next))
+                } else if *next == exit {
+                    Some((condition.clone(), *target))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        let do_while = match &blocks[&latch].terminator {
+            Terminator::Branch { condition, target, next } if *target == header && *next == exit => Some(condition.clone()),
+            Terminator::Branch { condition, target, next } if *next == header && *target == exit => Some(condition.negate()),
+            _ => None,
+        };
+        let continuation = if blocks.contains_key(&exit) { Some(exit) } else { None };
+        if let Some((condition, body)) = while_loop {
+            self.visited.insert(header);
+            if self.labels.contains(&header) {
+                self.line(indent, &format!("label_{}:", header));
+            }
+            self.line(indent, &format!("while ({}) {{", condition.render()));
+            self.sequence(body, Context { stop: Some(header), ..inner }, indent + 1, false);
+            self.line(indent, "}");
+            return continuation;
+        }
+        if let Some(condition) = do_while {
+            let (length, visited, jumped_to) = (self.text.len(), self.visited.clone(), self.jumped_to.clone());
+            self.line(indent, "do {");
+            self.sequence(header, Context { latch: Some(latch), ..inner }, indent + 1, true);
+            if self.visited.contains(&latch) {
+                self.line(indent, &format!("}} while ({});", condition.render()));
+                return continuation;
+            }
+            // the body left the loop before reaching the latch, its condition can't be used
+            self.text.truncate(length);
+            self.visited = visited;
+            self.jumped_to = jumped_to;
+        }
+        self.line(indent, "while (1) {");
+        self.sequence(header, inner, indent + 1, true);
+        self.line(indent, "}");
+        return continuation;
+    }
+}
+
+struct Decompiler<'a> {
+    code: &'a [i64],
+    functions: BTreeMap<usize, Function>,
+    /// Addresses written through parameters in position mode. Parameters stored there are computed at runtime.
+    patched: BTreeSet<usize>,
+}
+
+impl<'a> Decompiler<'a> {
+    fn new(code: &'a [i64]) -> Decompiler<'a> {
+        let mut functions = BTreeMap::new();
+        let mut pending = vec![MAIN];
+        while let Some(entry) = pending.pop() {
+            if functions.contains_key(&entry) {
+                continue;
+            }
+            let function = Function::scan(code, entry);
+            for (line, _) in function.lines.values() {
+                if let Some(call) = call_at(code, line) {
+                    pending.push(call.callee);
+                }
+            }
+            functions.insert(entry, function);
+        }
+        let mut patched = BTreeSet::new();
+        for (line, _) in functions.values().flat_map(|function| function.lines.values()) {
+            if let Some((opcode, parameters)) = instruction(line) {
+                if let Some(result_parameter) = opcode.result_parameter() {
+                    let parameter = parameters[result_parameter - 1];
+                    if parameter.mode == ParameterMode::Position && parameter.value >= 0 {
+                        patched.insert(parameter.value as usize);
+                    }
+                }
+            }
+        }
+        let mut decompiler = Decompiler { code, functions, patched };
+        decompiler.count_arguments();
+        return decompiler;
+    }
+
+    /// A function's arguments are the stack slots right after the return address that its callers write before calling.
+    fn count_arguments(&mut self) {
+        let mut arguments: BTreeMap<usize, usize> = BTreeMap::new();
+        for function in self.functions.values().filter(|function| function.tracked) {
+            let line_ending_at: BTreeMap<usize, &(DisassembledLine, Option<i64>)> =
+                function.lines.values().map(|entry| (entry.0.address + entry.0.words.len(), entry)).collect();
+            for (line, base) in function.lines.values() {
+                let call = match call_at(self.code, line) {
+                    Some(call) => call,
+                    None => continue,
+                };
+                let return_slot = match base.and_then(|base| base.checked_add(call.offset)) {
+                    Some(return_slot) => return_slot,
+                    None => continue,
+                };
+                let mut slots = BTreeSet::new();
+                let mut address = call.store;
+                while let Some((previous, previous_base)) = line_ending_at.get(&address) {
+                    let written_slot = match instruction(previous) {
+                        Some((opcode, parameters)) => opcode
+                            .result_parameter()
+                            .map(|parameter_id| parameters[parameter_id - 1])
+                            .filter(|parameter| parameter.mode == ParameterMode::Relative)
+                            .and_then(|parameter| previous_base.and_then(|base| base.checked_add(parameter.value))),
+                        None => None,
+                    };
+                    match written_slot.and_then(|slot| slot.checked_sub(return_slot)) {
+                        Some(offset) if offset > 0 => slots.insert(offset),
+                        _ => break,
+                    };
+                    address = previous.address;
+                }
+                let count = (1..).take_while(|slot| slots.contains(slot)).count();
+                let entry = arguments.entry(call.callee).or_insert(0);
+                *entry = (*entry).max(count);
+            }
+        }
+        for (entry, count) in arguments {
+            if let Some(function) = self.functions.get_mut(&entry) {
+                function.arguments = count;
+            }
+        }
+    }
+
+    /// All memory cells named as globals.
+    fn globals(&self) -> BTreeSet<usize> {
+        let mut globals = BTreeSet::new();
+        for function in self.functions.values() {
+            for (line, base) in function.lines.values() {
+                let parameters = match instruction(line) {
+                    Some((_, parameters)) => parameters,
+                    None => continue,
+                };
+                for (parameter_id, parameter) in parameters.iter().enumerate() {
+                    let word = line.address + parameter_id + 1;
+                    if self.patched.contains(&word) {
+                        globals.insert(word);
+                        continue;
+                    }
+                    let address = match (parameter.mode, base) {
+                        (ParameterMode::Position, _) => Some(parameter.value),
+                        (ParameterMode::Relative, Some(base)) if function.entry == MAIN && function.tracked => {
+                            base.checked_add(parameter.value)
+                        }
+                        _ => continue,
+                    };
+                    if let Some(Ok(address)) = address.map(usize::try_from) {
+                        globals.insert(address);
+                    }
+                }
+            }
+        }
+        return globals;
+    }
+
+    fn slot_name(&self, function: &Function, slot: i64) -> String {
+        if function.entry == MAIN {
+            return match slot {
+                slot if slot >= 0 => format!("var_{}", slot),
+                _ => format!("mem[{}]", slot),
+            };
+        }
+        let offset = i128::from(slot) - i128::from(function.return_slot.unwrap_or(0));
+        return match offset {
+            0 => "return_address".to_string(),
+            offset if offset > 0 && offset as usize <= function.arguments => format!("arg{}", offset),
+            offset if offset > 0 => format!("local{}", offset),
+            _ => format!("frame[{}]", offset),
+        };
+    }
+
+    /// Names a parameter, like it's used in an expression or as an assignment target.
+    fn operand(&self, function: &Function, line: &DisassembledLine, parameter_id: usize) -> String {
+        let parameter = match instruction(line) {
+            Some((_, parameters)) => parameters[parameter_id - 1],
+            None => return "?".to_string(),
+        };
+        let word = line.address + parameter_id;
+        if self.patched.contains(&word) {
+            return match parameter.mode {
+                ParameterMode::Immediate => format!("var_{}", word),
+                ParameterMode::Position => format!("mem[var_{}]", word),
+                ParameterMode::Relative => format!("rb[var_{}]", word),
+            };
+        }
+        let slot = function.lines[&line.address].1.and_then(|base| base.checked_add(parameter.value));
+        return match (parameter.mode, slot) {
+            (ParameterMode::Immediate, _) => parameter.value.to_string(),
+            (ParameterMode::Position, _) if parameter.value >= 0 => format!("var_{}", parameter.value),
+            (ParameterMode::Position, _) => format!("mem[{}]", parameter.value),
+            (ParameterMode::Relative, Some(slot)) if function.tracked => self.slot_name(function, slot),
+            (ParameterMode::Relative, _) => format!("rb[{}]", parameter.value),
+        };
+    }
+
+    fn statement(&self, function: &Function, line: &DisassembledLine, opcode: Opcode) -> Option<String> {
+        let operand = |parameter_id| self.operand(function, line, parameter_id);
+        return match opcode {
+            Opcode::Add | Opcode::Mul => {
+                let (first, second, result) = (operand(1), operand(2), operand(3));
+                let (operator, identity) = if opcode == Opcode::Add { ("+", "0") } else { ("*", "1") };
+                let negative = second.strip_prefix('-').filter(|_| second.parse::<i64>().is_ok());
+                Some(match () {
+                    _ if first == identity => format!("{} = {};", result, second),
+                    _ if second == identity => format!("{} = {};", result, first),
+                    _ if opcode == Opcode::Add && first == result && negative.is_some() => {
+                        format!("{} -= {};", result, negative.unwrap())
+                    }
+                    _ if first == result => format!("{} {}= {};", result, operator, second),
+                    _ if second == result => format!("{} {}= {};", result, operator, first),
+                    _ if opcode == Opcode::Add && negative.is_some() => format!("{} = {} - {};", result, first, negative.unwrap()),
+                    _ => format!("{} = {} {} {};", result, first, operator, second),
+                })
+            }
+            Opcode::LessThan => Some(format!("{} = {} < {};", operand(3), operand(1), operand(2))),
+            Opcode::Equals => Some(format!("{} = {} == {};", operand(3), operand(1), operand(2))),
+            Opcode::Input => Some(format!("{} = input();", operand(1))),
+            Opcode::Output => Some(format!("output({});", operand(1))),
+            Opcode::RelativeBaseOffset if function.tracked => None,
+            Opcode::RelativeBaseOffset => Some(format!("rb += {};", operand(1))),
+            _ => None,
+        };
+    }
+
+    fn call_statement(&self, function: &Function, line: &DisassembledLine, call: &Call) -> String {
+        let callee = &self.functions[&call.callee];
+        let base = function.lines[&line.address].1;
+        let arguments: Vec<String> = (1..=callee.arguments as i64)
+            .map(|argument| {
+                let slot = base.filter(|_| function.tracked).and_then(|base| base.checked_add(call.offset)?.checked_add(argument));
+                match slot {
+                    Some(slot) => self.slot_name(function, slot),
+                    None => format!("rb[{}]", i128::from(call.offset) + i128::from(argument)),
+                }
+            })
+            .collect();
+        return format!("f_{}({});", call.callee, arguments.join(", "));
+    }
+
+    fn blocks(&self, function: &Function) -> BTreeMap<usize, Block> {
+        let calls: Vec<Call> = function.lines.values().filter_map(|(line, _)| call_at(self.code, line)).collect();
+        let stores: BTreeSet<usize> = calls.iter().map(|call| call.store).collect();
+        let mut blocks = BTreeMap::new();
+        for start in function.leaders.iter().cloned().filter(|start| function.lines.contains_key(start)) {
+            let mut statements = Vec::new();
+            let mut address = start;
+            let terminator = loop {
+                let (line, base) = &function.lines[&address];
+                let next = address + line.words.len();
+                let (opcode, parameters) = match instruction(line) {
+                    Some(instruction) => instruction,
+                    None => break Terminator::Stop(format!("invalid(); // {} at {}", line.decoded, address)),
+                };
+                match opcode {
+                    Opcode::Terminate => break Terminator::Halt,
+                    Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                        if let Some(call) = call_at(self.code, line) {
+                            statements.push(self.call_statement(function, line, &call));
+                        } else {
+                            let condition = Condition {
+                                expression: self.operand(function, line, 1),
+                                negated: opcode == Opcode::JumpIfFalse,
+                            };
+                            let taken = is_taken(opcode, parameters[0]);
+                            let target = parameters[1];
+                            let is_return = target.mode == ParameterMode::Relative
+                                && function.entry != MAIN
+                                && function.tracked
+                                && function.return_slot.is_some()
+                                && base.and_then(|base| base.checked_add(target.value)) == function.return_slot;
+                            let target_address = match target.mode {
+                                ParameterMode::Immediate => usize::try_from(target.value).ok(),
+                                _ => None,
+                            };
+                            let target_name = format!("*{}", self.operand(function, line, 2));
+                            match (taken, target_address) {
+                                (Some(false), _) => (),
+                                (Some(true), Some(target)) => break Terminator::Next(target),
+                                (None, Some(target)) => break Terminator::Branch { condition, target, next },
+                                (Some(true), None) if is_return => break Terminator::Return,
+                                (Some(true), None) => break Terminator::Stop(format!("goto {};", target_name)),
+                                (None, None) if is_return => statements.push(format!("if ({}) return;", condition.render())),
+                                (None, None) => statements.push(format!("if ({}) goto {};", condition.render(), target_name)),
+                            }
+                        }
+                    }
+                    _ if stores.contains(&address) => (),
+                    _ => statements.extend(self.statement(function, line, opcode)),
+                }
+                if function.leaders.contains(&next) || !function.lines.contains_key(&next) {
+                    break Terminator::Next(next);
+                }
+                address = next;
+            };
+            let end = function.lines.range(..=address).next_back().map_or(address, |(_, (line, _))| line.address + line.words.len());
+            blocks.insert(start, Block { end, statements, terminator });
+        }
+        return blocks;
+    }
+
+    fn function(&self, function: &Function) -> String {
+        let blocks = self.blocks(function);
+        let mut structurer = Structurer {
+            blocks: &blocks,
+            labels: BTreeSet::new(),
+            jumped_to: BTreeSet::new(),
+            visited: BTreeSet::new(),
+            text: String::new(),
+        };
+        // labels are only known after a first pass
+        for _ in 0..2 {
+            structurer.labels = structurer.jumped_to.clone();
+            structurer.visited.clear();
+            structurer.text.clear();
+            structurer.sequence(function.entry, Context::default(), 1, false);
+        }
+        let signature = match function.entry {
+            MAIN => "void main()".to_string(),
+            entry => {
+                let arguments: Vec<String> = (1..=function.arguments).map(|argument| format!("arg{}", argument)).collect();
+                format!("void f_{}({})", entry, arguments.join(", "))
+            }
+        };
+        return format!("{} {{\n{}}}\n", signature, structurer.text);
+    }
+}
+
+/// Decompiles the code reachable from address 0, and all functions it calls.
+pub fn decompile(code: &[i64]) -> String {
+    let decompiler = Decompiler::new(code);
+    let mut text = String::new();
+    for address in decompiler.globals() {
+        text += &format!("int var_{} = {};\n", address, code.get(address).unwrap_or(&0));
+    }
+    for function in decompiler.functions.values() {
+        text += &format!("\n{}", decompiler.function(function));
+    }
+    return text;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+
+    fn body(source: &str) -> String {
+        let text = decompile(&assemble(source).unwrap());
+        let start = text.find("void main() {\n").unwrap();
+        return text[start..].to_string();
+    }
+
+    #[test]
+    fn if_else() {
+        let source = "
+                    IN [x]
+                    LT [x], #10, [small]
+                    JZ [small], #else
+                    OUT #1
+                    JNZ #1, #end
+            else:   OUT #2
+            end:    HLT
+            x:      .data 0
+            small:  .data 0
+        ";
+        let text = decompile(&assemble(source).unwrap());
+        assert!(text.starts_with("int var_17 = 0;\nint var_18 = 0;\n\nvoid main() {\n"));
+        assert!(text.ends_with(
+            "    var_17 = input();
+    var_18 = var_17 < 10;
+    if (var_18) {
+        output(1);
+    } else {
+        output(2);
+    }
+    halt();
+}
+"
+        ));
+    }
+
+    #[test]
+    fn loops() {
+        // outputs 3, 2, 1, then counts up to 5 with a do/while loop
+        let source = "
+                    ADD #3, #0, [i]
+            loop:   JZ [i], #done
+                    OUT [i]
+                    ADD [i], #-1, [i]
+                    JNZ #1, #loop
+            done:   ADD [i], #1, [i]
+                    EQ [i], #5, [c]
+                    JZ [c], #done
+                    HLT
+            i:      .data 0
+            c:      .data 0
+        ";
+        assert_eq!(
+            body(source),
+            "void main() {
+    var_28 = 3;
+    while (var_28) {
+        output(var_28);
+        var_28 -= 1;
+    }
+    do {
+        var_28 += 1;
+        var_29 = var_28 == 5;
+    } while (!var_29);
+    halt();
+}
+"
+        );
+    }
+
+    #[test]
+    fn functions_and_stack_slots() {
+        // main calls sum(4, 5) and outputs the result, which is returned in the first argument
+        let source = "
+                    ARB #100
+                    ADD #4, #0, rb+1
+                    ADD #5, #0, rb+2
+                    ADD #call_end, #0, rb+0
+                    JNZ #1, #sum
+            call_end: OUT rb+1
+                    HLT
+            sum:    ARB #4
+                    ADD rb-3, rb-2, rb-1
+                    ADD rb-1, #0, rb-3
+                    ARB #-4
+                    JZ #0, rb+0
+        ";
+        let text = decompile(&assemble(source).unwrap());
+        assert!(text.contains(
+            "void main() {
+    var_101 = 4;
+    var_102 = 5;
+    f_20(var_101, var_102);
+    output(var_101);
+    halt();
+}
+"
+        ));
+        assert!(text.contains(
+            "void f_20(arg1, arg2) {
+    local3 = arg1 + arg2;
+    arg1 = local3;
+    return;
+}
+"
+        ));
+    }
+
+    #[test]
+    fn computed_addresses_and_gotos() {
+        // outputs the array element at index [i], then jumps back into the middle of the loop
+        let source = "
+            start:  ADD #array, [i], [5]
+                    OUT [0]
+                    JZ [i], #other
+            back:   IN [i]
+                    JNZ #1, #start
+            other:  JNZ [i], #back
+                    HLT
+            i:      .data 0
+            array:  .data 7
+        ";
+        assert_eq!(
+            body(source),
+            "void main() {
+    while (1) {
+        var_5 = 19 + var_18;
+        output(mem[var_5]);
+        if (!var_18) break;
+        label_9:
+        var_18 = input();
+        continue;
+    }
+    if (var_18) goto label_9;
+    halt();
+}
+"
+        );
+    }
+
+    #[test]
+    fn overflowing_relative_base() {
+        // the relative base is unknown after it overflows, so stack slots stay relative to it
+        assert_eq!(decompile(&[109, i64::MAX, 109, 1, 99]), "\nvoid main() {\n    rb += 9223372036854775807;\n    rb += 1;\n    halt();\n}\n");
+        assert_eq!(decompile(&[109, i64::MAX, 204, 1, 99]), "\nvoid main() {\n    output(rb[1]);\n    halt();\n}\n");
+    }
+}
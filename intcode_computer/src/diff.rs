@@ -0,0 +1,159 @@
+//! Compares two states of a program, e.g. to find the memory cells holding a game's score, see `Program::diff`.
+use crate::{Memory, Program};
+use std::fmt;
+
+/// A memory cell whose value differs. Values are wide, like the ones returned by `Program::read_wide_memory`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CellChange {
+    pub address: usize,
+    pub old_value: i128,
+    pub new_value: i128,
+}
+
+/// Changed cells at contiguous addresses.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ChangedRange {
+    pub changes: Vec<CellChange>,
+}
+
+impl ChangedRange {
+    pub fn start(&self) -> usize {
+        return self.changes[0].address;
+    }
+
+    /// One past the last changed address.
+    pub fn end(&self) -> usize {
+        return self.start() + self.changes.len();
+    }
+}
+
+impl fmt::Display for ChangedRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let old_values: Vec<String> = self.changes.iter().map(|change| change.old_value.to_string()).collect();
+        let new_values: Vec<String> = self.changes.iter().map(|change| change.new_value.to_string()).collect();
+        if self.changes.len() == 1 {
+            write!(f, "{}", self.start())?;
+        } else {
+            write!(f, "{}..{}", self.start(), self.end())?;
+        }
+        write!(f, ": {} -> {}", old_values.join(" "), new_values.join(" "))
+    }
+}
+
+/// Differences between two states of a program. Registers are given as old and new value, if they differ.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ProgramDiff {
+    pub instruction_pointer: Option<(usize, usize)>,
+    pub relative_base: Option<(usize, usize)>,
+    /// In ascending order of their addresses.
+    pub ranges: Vec<ChangedRange>,
+}
+
+impl ProgramDiff {
+    pub fn is_empty(&self) -> bool {
+        return self.instruction_pointer.is_none() && self.relative_base.is_none() && self.ranges.is_empty();
+    }
+
+    /// All changed cells, in ascending order of their addresses.
+    pub fn changes(&self) -> impl Iterator<Item = &CellChange> {
+        return self.ranges.iter().flat_map(|range| range.changes.iter());
+    }
+}
+
+impl fmt::Display for ProgramDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((old, new)) = self.instruction_pointer {
+            writeln!(f, "instruction pointer: {} -> {}", old, new)?;
+        }
+        if let Some((old, new)) = self.relative_base {
+            writeln!(f, "relative base: {} -> {}", old, new)?;
+        }
+        for range in self.ranges.iter() {
+            writeln!(f, "{}", range)?;
+        }
+        Ok(())
+    }
+}
+
+fn register_change(old: usize, new: usize) -> Option<(usize, usize)> {
+    return if old == new { None } else { Some((old, new)) };
+}
+
+impl<M: Memory> Program<M> {
+    /// Compares this program to another state of it, e.g. a clone taken before running it.
+    /// Changes are reported from this program's values to the other one's.
+    pub fn diff<N: Memory>(&self, other: &Program<N>) -> ProgramDiff {
+        let mut addresses: Vec<usize> = self.memory.cells().into_iter().map(|cell| cell.0).collect();
+        addresses.extend(other.memory.cells().into_iter().map(|cell| cell.0));
+        addresses.extend(self.wide_cells.keys().chain(other.wide_cells.keys()));
+        addresses.sort_unstable();
+        addresses.dedup();
+        let mut ranges: Vec<ChangedRange> = Vec::new();
+        for address in addresses {
+            let (old_value, new_value) = (self.read_wide_memory(address), other.read_wide_memory(address));
+            if old_value == new_value {
+                continue;
+            }
+            let change = CellChange { address, old_value, new_value };
+            match ranges.last_mut() {
+                Some(range) if range.end() == address => range.changes.push(change),
+                _ => ranges.push(ChangedRange { changes: vec![change] }),
+            }
+        }
+        return ProgramDiff {
+            instruction_pointer: register_change(self.instruction_pointer, other.instruction_pointer),
+            relative_base: register_change(self.relative_base, other.relative_base),
+            ranges,
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{OverflowPolicy, SparseMemory};
+
+    #[test]
+    fn changed_ranges_and_registers() {
+        // writes its input to [20] and [21], 7 to [23], then moves the relative base to 3
+        let code = [3, 20, 1001, 20, 0, 21, 1101, 3, 4, 23, 109, 3, 99];
+        let before = Program::init(&code);
+        let mut after = before.clone();
+        after.run(vec![5]).unwrap();
+        let diff = before.diff(&after);
+        assert_eq!(diff.instruction_pointer, Some((0, 12)));
+        assert_eq!(diff.relative_base, Some((0, 3)));
+        assert_eq!(
+            diff.ranges,
+            [
+                ChangedRange {
+                    changes: vec![
+                        CellChange { address: 20, old_value: 0, new_value: 5 },
+                        CellChange { address: 21, old_value: 0, new_value: 5 },
+                    ]
+                },
+                ChangedRange { changes: vec![CellChange { address: 23, old_value: 0, new_value: 7 }] },
+            ]
+        );
+        assert_eq!(diff.changes().map(|change| change.address).collect::<Vec<usize>>(), [20, 21, 23]);
+        assert_eq!(diff.to_string(), "instruction pointer: 0 -> 12\nrelative base: 0 -> 3\n20..22: 0 0 -> 5 5\n23: 0 -> 7\n");
+        assert_eq!(after.diff(&before).ranges[1].changes[0], CellChange { address: 23, old_value: 7, new_value: 0 });
+        assert!(after.diff(&after.clone()).is_empty());
+    }
+
+    #[test]
+    fn memory_backends_and_wide_values() {
+        // [5] = [5] * [5]
+        let code = [2, 5, 5, 5, 99, 1 << 40];
+        let mut paged = Program::init(&code);
+        let mut sparse = Program::with_memory(SparseMemory::from_code(&code));
+        assert!(paged.diff(&sparse).is_empty());
+        sparse.set_overflow_policy(OverflowPolicy::Wide);
+        sparse.run(Vec::new()).unwrap();
+        paged.set_memory(2, 3);
+        let diff = paged.diff(&sparse);
+        assert_eq!(diff.instruction_pointer, Some((0, 4)));
+        assert_eq!(diff.relative_base, None);
+        assert_eq!(diff.to_string(), "instruction pointer: 0 -> 4\n2: 3 -> 5\n5: 1099511627776 -> 1208925819614629174706176\n");
+    }
+}
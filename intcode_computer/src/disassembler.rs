@@ -0,0 +1,219 @@
+use crate::{encode_instruction, parse_instruction, Memory, Opcode, ParameterMode, Program};
+use std::fmt;
+
+/// A single parameter of a decoded instruction, as it is stored in memory.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Parameter {
+    pub mode: ParameterMode,
+    pub value: i64,
+}
+
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mode {
+            ParameterMode::Position => write!(f, "[{}]", self.value),
+            ParameterMode::Immediate => write!(f, "#{}", self.value),
+            ParameterMode::Relative if self.value < 0 => write!(f, "rb{}", self.value),
+            ParameterMode::Relative => write!(f, "rb+{}", self.value),
+        }
+    }
+}
+
+/// What a word (or a group of words) at some address decodes to.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Decoded {
+    Instruction {
+        opcode: Opcode,
+        parameters: Vec<Parameter>,
+    },
+    Data(i64),
+}
+
+impl Decoded {
+    /// The words this decodes from.
+    pub fn encode(&self) -> Vec<i64> {
+        let (opcode, parameters) = match self {
+            Decoded::Instruction { opcode, parameters } => (opcode, parameters),
+            Decoded::Data(value) => return vec![*value],
+        };
+        let mut words = vec![encode_instruction(*opcode, parameters.iter().map(|parameter| parameter.mode))];
+        words.extend(parameters.iter().map(|parameter| parameter.value));
+        return words;
+    }
+}
+
+impl fmt::Display for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decoded::Instruction { opcode, parameters } => {
+                write!(f, "{}", opcode.mnemonic())?;
+                for (parameter_id, parameter) in parameters.iter().enumerate() {
+                    let separator = if parameter_id == 0 { " " } else { ", " };
+                    write!(f, "{}{}", separator, parameter)?;
+                }
+                return Ok(());
+            }
+            Decoded::Data(value) => write!(f, ".data {}", value),
+        }
+    }
+}
+
+/// One line of a listing: the address, the raw words and what they decode to.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DisassembledLine {
+    pub address: usize,
+    pub words: Vec<i64>,
+    pub decoded: Decoded,
+}
+
+impl fmt::Display for DisassembledLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let words: Vec<String> = self.words.iter().map(|word| word.to_string()).collect();
+        write!(f, "{:>6}: {:<32} {}", self.address, words.join(" "), self.decoded)
+    }
+}
+
+/// Decodes the words starting at the given address into a single line.
+/// `words` has to start with the word stored at `address`. Words that aren't a complete, valid instruction are data.
+pub fn decode(address: usize, words: &[i64]) -> DisassembledLine {
+    let data = DisassembledLine {
+        address,
+        words: words.iter().take(1).cloned().collect(),
+        decoded: Decoded::Data(*words.first().unwrap_or(&0)),
+    };
+    let (opcode, pm1, pm2, pm3) = match words.first().map(|word| parse_instruction(*word)) {
+        Some(Ok(instruction)) => instruction,
+        _ => return data,
+    };
+    let parameter_count = opcode.parameter_count();
+    if words.len() <= parameter_count {
+        return data;
+    }
+    let modes = [pm1, pm2, pm3];
+    // mode digits of missing parameters wouldn't survive encoding the instruction again
+    if encode_instruction(opcode, modes[..parameter_count].iter().cloned()) != words[0] {
+        return data;
+    }
+    if let Some(result_parameter) = opcode.result_parameter() {
+        if modes[result_parameter - 1] == ParameterMode::Immediate {
+            return data;
+        }
+    }
+    let parameters = (0..parameter_count)
+        .map(|parameter_id| Parameter {
+            mode: modes[parameter_id],
+            value: words[parameter_id + 1],
+        })
+        .collect();
+    return DisassembledLine {
+        address,
+        words: words[..parameter_count + 1].to_vec(),
+        decoded: Decoded::Instruction { opcode, parameters },
+    };
+}
+
+/// Decodes the whole code linearly, from the first to the last word.
+pub fn disassemble(code: &[i64]) -> Vec<DisassembledLine> {
+    let mut lines = Vec::new();
+    let mut address = 0;
+    while address < code.len() {
+        let line = decode(address, &code[address..]);
+        address += line.words.len();
+        lines.push(line);
+    }
+    return lines;
+}
+
+/// Decodes the instruction at the given address of a program's memory.
+pub fn disassemble_at<M: Memory>(program: &Program<M>, address: usize) -> DisassembledLine {
+    let words: Vec<i64> = (address..address.saturating_add(4))
+        .map(|address| program.read_memory(address))
+        .collect();
+    return decode(address, &words);
+}
+
+/// Renders an annotated listing of the given code, one line per instruction or data word.
+pub fn listing(code: &[i64]) -> String {
+    let mut listing = String::new();
+    for line in disassemble(code) {
+        listing += &format!("{}\n", line);
+    }
+    return listing;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parameters_are_rendered_per_mode() {
+        let parameter = |mode, value| Parameter { mode, value }.to_string();
+        assert_eq!(parameter(ParameterMode::Position, 12), "[12]");
+        assert_eq!(parameter(ParameterMode::Immediate, -5), "#-5");
+        assert_eq!(parameter(ParameterMode::Relative, 3), "rb+3");
+        assert_eq!(parameter(ParameterMode::Relative, -8), "rb-8");
+    }
+
+    #[test]
+    fn decode_instruction() {
+        let line = decode(7, &[1101, 100, -1, 4, 0]);
+        assert_eq!(line.address, 7);
+        assert_eq!(line.words, [1101, 100, -1, 4]);
+        assert_eq!(line.decoded.to_string(), "ADD #100, #-1, [4]");
+        assert_eq!(decode(0, &[204, -8]).decoded.to_string(), "OUT rb-8");
+        assert_eq!(decode(0, &[99]).decoded.to_string(), "HLT");
+        assert_eq!(decode(3, &[21101, 4, -2, 5]).decoded.encode(), [21101, 4, -2, 5]);
+    }
+
+    #[test]
+    fn invalid_words_are_data() {
+        assert_eq!(decode(0, &[42, 1, 2]).decoded, Decoded::Data(42));
+        // truncated instruction
+        assert_eq!(decode(0, &[1, 9, 10]).decoded, Decoded::Data(1));
+        // result parameter in immediate mode
+        assert_eq!(decode(0, &[11101, 1, 1, 5]).decoded, Decoded::Data(11101));
+        // mode digits for parameters the opcode doesn't have
+        assert_eq!(decode(0, &[1199]).decoded, Decoded::Data(1199));
+        assert_eq!(decode(0, &[10004, 5, 99]).decoded, Decoded::Data(10004));
+        let words: Vec<i64> = disassemble(&[10004, 5, 99]).iter().flat_map(|line| line.decoded.encode()).collect();
+        assert_eq!(words, [10004, 5, 99]);
+        assert_eq!(decode(0, &[]).words, []);
+    }
+
+    #[test]
+    fn disassemble_code() {
+        let code = [1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+        let lines = disassemble(&code);
+        let decoded: Vec<String> = lines.iter().map(|line| line.decoded.to_string()).collect();
+        assert_eq!(
+            decoded,
+            ["ADD [9], [10], [3]", "MUL [3], [11], [0]", "HLT", ".data 30", ".data 40", ".data 50"]
+        );
+        let addresses: Vec<usize> = lines.iter().map(|line| line.address).collect();
+        assert_eq!(addresses, [0, 4, 8, 9, 10, 11]);
+    }
+
+    #[test]
+    fn listing_contains_address_and_words() {
+        let listing = listing(&[109, 19, 204, -8, 99]);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("     0: 109 19 "));
+        assert!(lines[0].ends_with(" ARB #19"));
+        assert!(lines[1].starts_with("     2: 204 -8 "));
+        assert!(lines[1].ends_with(" OUT rb-8"));
+        assert!(lines[2].ends_with(" HLT"));
+    }
+
+    #[test]
+    fn disassemble_program_memory() {
+        let mut program = Program::init(&[1002, 4, 3, 4, 33]);
+        program.step(None).unwrap();
+        assert_eq!(disassemble_at(&program, 0).decoded.to_string(), "MUL [4], #3, [4]");
+        assert_eq!(disassemble_at(&program, 4).decoded.to_string(), "HLT");
+        program.set_memory(usize::MAX - 1, 1101);
+        let line = disassemble_at(&program, usize::MAX - 1);
+        assert_eq!(line.address, usize::MAX - 1);
+        assert_eq!(line.words.len(), 1);
+    }
+}
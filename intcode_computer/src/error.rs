@@ -0,0 +1,78 @@
+use crate::Opcode;
+use std::error::Error;
+use std::fmt;
+
+/// Describes why an instruction couldn't be executed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorReason {
+    InvalidOpcode(i64),
+    InvalidParameterMode { parameter_id: usize, mode: i64 },
+    MissingInput,
+    NegativeAddress { parameter_id: usize, address: i64 },
+    ImmediateModeResult { parameter_id: usize },
+    InvalidJumpTarget(i64),
+    NegativeRelativeBase(i64),
+    /// Adding an offset to the relative base doesn't fit into 64 bits.
+    RelativeBaseOverflow { relative_base: usize, offset: i64 },
+    UnexpectedInput,
+    UnexpectedOutput,
+    ArithmeticOverflow { opcode: Opcode, first_operand: i128, second_operand: i128 },
+    /// A custom opcode's handler used a parameter its instruction doesn't have.
+    UnknownParameter { parameter_id: usize },
+    /// A value that doesn't fit into 64 bits is used where only those are allowed, see `OverflowPolicy::Wide`.
+    WideValue(i128),
+}
+
+impl fmt::Display for ErrorReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorReason::InvalidOpcode(opcode) => write!(f, "invalid opcode {}", opcode),
+            ErrorReason::InvalidParameterMode { parameter_id, mode } => {
+                write!(f, "invalid mode {} for parameter {}", mode, parameter_id)
+            }
+            ErrorReason::MissingInput => write!(f, "input instruction without any given input"),
+            ErrorReason::NegativeAddress { parameter_id, address } => {
+                write!(f, "parameter {} refers to negative address {}", parameter_id, address)
+            }
+            ErrorReason::ImmediateModeResult { parameter_id } => {
+                write!(f, "parameter {} is a result address but uses immediate mode", parameter_id)
+            }
+            ErrorReason::InvalidJumpTarget(target) => write!(f, "invalid jump target {}", target),
+            ErrorReason::NegativeRelativeBase(relative_base) => {
+                write!(f, "relative base would become negative ({})", relative_base)
+            }
+            ErrorReason::RelativeBaseOverflow { relative_base, offset } => {
+                write!(f, "relative base {} plus {} overflows", relative_base, offset)
+            }
+            ErrorReason::UnexpectedInput => write!(f, "unexpected input instruction"),
+            ErrorReason::UnexpectedOutput => write!(f, "unexpected output instruction"),
+            ErrorReason::ArithmeticOverflow { opcode, first_operand, second_operand } => {
+                write!(f, "{} {}, {} overflows", opcode.mnemonic(), first_operand, second_operand)
+            }
+            ErrorReason::UnknownParameter { parameter_id } => write!(f, "instruction has no parameter {}", parameter_id),
+            ErrorReason::WideValue(value) => write!(f, "value {} doesn't fit into 64 bits", value),
+        }
+    }
+}
+
+/// Error returned when a program fails to execute an instruction.
+/// The program's state is left untouched, so it still points at the failing instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntcodeError {
+    pub instruction_pointer: usize,
+    /// The raw instruction word found at the instruction pointer.
+    pub opcode: i64,
+    pub reason: ErrorReason,
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{} (opcode {}): {}",
+            self.instruction_pointer, self.opcode, self.reason
+        )
+    }
+}
+
+impl Error for IntcodeError {}
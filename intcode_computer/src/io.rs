@@ -0,0 +1,188 @@
+//! Sources of input and sinks for output values, used by `Program::run_with`.
+//!
+//! A single value can provide both, or a tuple of a source and a sink can be used:
+//!
+//! ```
+//! use intcode_computer::Program;
+//! use std::collections::VecDeque;
+//!
+//! let mut program = Program::init(&[3, 0, 4, 0, 99]);
+//! let mut outputs = Vec::new();
+//! program.run_with(&mut (VecDeque::from(vec![42]), |value| outputs.push(value))).unwrap();
+//! assert_eq!(outputs, [42]);
+//! ```
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+use std::sync::mpsc::{Receiver, Sender};
+
+pub trait InputSource {
+    /// Provides the value for the next input instruction, None if there is no more input.
+    fn next_input(&mut self) -> Option<i64>;
+}
+
+pub trait OutputSink {
+    fn write_output(&mut self, value: i64);
+}
+
+/// Ordered queue, inputs are taken from the front.
+impl InputSource for VecDeque<i64> {
+    fn next_input(&mut self) -> Option<i64> {
+        return self.pop_front();
+    }
+}
+
+/// Closure computing inputs lazily.
+impl<F: FnMut() -> Option<i64>> InputSource for F {
+    fn next_input(&mut self) -> Option<i64> {
+        return self();
+    }
+}
+
+/// Blocks until the next value is received.
+impl InputSource for Receiver<i64> {
+    fn next_input(&mut self) -> Option<i64> {
+        return self.recv().ok();
+    }
+}
+
+/// Takes inputs from any iterator, see `from_iter`.
+pub struct IterInput<I>(pub I);
+
+impl<I: Iterator<Item = i64>> InputSource for IterInput<I> {
+    fn next_input(&mut self) -> Option<i64> {
+        return self.0.next();
+    }
+}
+
+pub fn from_iter<I: IntoIterator<Item = i64>>(values: I) -> IterInput<I::IntoIter> {
+    return IterInput(values.into_iter());
+}
+
+/// Reads one value per line from stdin, asking again on lines that aren't a number.
+pub struct StdinInput;
+
+impl InputSource for StdinInput {
+    fn next_input(&mut self) -> Option<i64> {
+        let stdin = io::stdin();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if stdin.lock().read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            match line.trim().parse() {
+                Ok(value) => return Some(value),
+                Err(_) => eprintln!("not a number: '{}', please try again", line.trim()),
+            }
+        }
+    }
+}
+
+impl OutputSink for Vec<i64> {
+    fn write_output(&mut self, value: i64) {
+        self.push(value);
+    }
+}
+
+impl OutputSink for VecDeque<i64> {
+    fn write_output(&mut self, value: i64) {
+        self.push_back(value);
+    }
+}
+
+impl<F: FnMut(i64)> OutputSink for F {
+    fn write_output(&mut self, value: i64) {
+        self(value);
+    }
+}
+
+/// Outputs are dropped silently once the receiving end is gone.
+impl OutputSink for Sender<i64> {
+    fn write_output(&mut self, value: i64) {
+        let _ = self.send(value);
+    }
+}
+
+/// Prints one value per line to stdout.
+pub struct StdoutOutput;
+
+impl OutputSink for StdoutOutput {
+    fn write_output(&mut self, value: i64) {
+        println!("{}", value);
+    }
+}
+
+impl<I: InputSource, O: OutputSink> InputSource for (I, O) {
+    fn next_input(&mut self) -> Option<i64> {
+        return self.0.next_input();
+    }
+}
+
+impl<I: InputSource, O: OutputSink> OutputSink for (I, O) {
+    fn write_output(&mut self, value: i64) {
+        self.1.write_output(value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ErrorReason, Program};
+
+    // outputs the sum of both inputs, twice
+    const ADD_INPUTS: [i64; 13] = [3, 0, 3, 1, 1, 0, 1, 0, 4, 0, 4, 0, 99];
+
+    fn run_add_inputs(io: &mut (impl InputSource + OutputSink)) -> Result<i64, ErrorReason> {
+        let mut program = Program::init(&ADD_INPUTS);
+        program.run_with(io).map_err(|error| error.reason)?;
+        return Ok(program.read_memory(0));
+    }
+
+    #[test]
+    fn inputs_are_consumed_in_order() {
+        let mut io = (VecDeque::from(vec![5, -3]), Vec::new());
+        assert_eq!(run_add_inputs(&mut io), Ok(2));
+        assert_eq!(io.1, [2, 2]);
+        let mut io = (from_iter(vec![1, 2]), VecDeque::new());
+        assert_eq!(run_add_inputs(&mut io), Ok(3));
+        assert_eq!(io.1, [3, 3]);
+    }
+
+    #[test]
+    fn closures_compute_inputs_lazily() {
+        let mut next_input = 10;
+        let mut outputs = Vec::new();
+        let input = || {
+            next_input += 1;
+            Some(next_input)
+        };
+        assert_eq!(run_add_inputs(&mut (input, |value| outputs.push(value))), Ok(23));
+        assert_eq!(outputs, [23, 23]);
+    }
+
+    #[test]
+    fn missing_input_fails() {
+        let mut io = (VecDeque::from(vec![5]), Vec::new());
+        assert_eq!(run_add_inputs(&mut io), Err(ErrorReason::MissingInput));
+    }
+
+    #[test]
+    fn single_value_as_source_and_sink() {
+        // feeds the last output plus one back as the next input
+        struct Echo(Vec<i64>);
+        impl InputSource for Echo {
+            fn next_input(&mut self) -> Option<i64> {
+                return Some(*self.0.last().unwrap_or(&1) + 1);
+            }
+        }
+        impl OutputSink for Echo {
+            fn write_output(&mut self, value: i64) {
+                self.0.push(value);
+            }
+        }
+        let mut echo = Echo(Vec::new());
+        let mut program = Program::init(&[3, 0, 4, 0, 3, 0, 4, 0, 99]);
+        program.run_with(&mut echo).unwrap();
+        assert_eq!(echo.0, [2, 3]);
+    }
+}
@@ -1,7 +1,52 @@
+#![allow(clippy::needless_return)]
 use std::convert::TryFrom;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::ops::RangeBounds;
+use std::sync::{Arc, Mutex};
 
-#[derive(Debug, PartialEq)]
+mod accesses;
+pub mod ascii;
+pub mod assembler;
+pub mod builder;
+pub mod cfg;
+pub mod custom;
+mod decode_cache;
+pub mod decompiler;
+pub mod diff;
+pub mod disassembler;
+mod error;
+pub mod io;
+pub mod memory;
+pub mod optimizer;
+pub mod overflow;
+pub mod parse;
+pub mod profile;
+pub mod self_modification;
+pub mod snapshot;
+pub mod solver;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod threaded;
+pub mod trace;
+pub mod undo;
+pub mod watchpoint;
+
+pub use builder::ProgramBuilder;
+use custom::{CustomOpcode, OpcodeDefinition};
+pub use error::{ErrorReason, IntcodeError};
+use decode_cache::{DecodeCache, Instruction};
+use io::{InputSource, OutputSink};
+use accesses::InstructionAccesses;
+use profile::Profile;
+use self_modification::SelfModificationDetector;
+pub use memory::{Memory, PagedMemory, SparseMemory};
+pub use overflow::OverflowPolicy;
+pub use parse::{parse_program_file, parse_program_reader, parse_program_str, ParseError};
+use trace::{Trace, TraceEntry};
+use undo::{UndoEntry, UndoLog};
+use watchpoint::{Access, WatchEvent, WatchKind, Watchpoints};
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum Opcode {
     Add,
     Mul,
@@ -13,23 +58,121 @@ pub enum Opcode {
     Equals,
     RelativeBaseOffset,
     Terminate,
+    /// Registered with `ProgramBuilder::opcode`.
+    Custom(OpcodeDefinition),
+}
+
+impl Opcode {
+    /// Number of parameters following the instruction word.
+    pub fn parameter_count(&self) -> usize {
+        return match self {
+            Opcode::Add | Opcode::Mul | Opcode::LessThan | Opcode::Equals => 3,
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => 2,
+            Opcode::Input | Opcode::Output | Opcode::RelativeBaseOffset => 1,
+            Opcode::Terminate => 0,
+            Opcode::Custom(definition) => definition.parameter_count,
+        };
+    }
+
+    /// The id of the parameter that describes the address the instruction writes to, if any.
+    pub fn result_parameter(&self) -> Option<usize> {
+        return match self {
+            Opcode::Add | Opcode::Mul | Opcode::LessThan | Opcode::Equals => Some(3),
+            Opcode::Input => Some(1),
+            Opcode::Custom(definition) => definition.result_parameter,
+            _ => None,
+        };
+    }
+
+    /// The number identifying the opcode in the last two digits of an instruction.
+    pub fn number(&self) -> i64 {
+        return match self {
+            Opcode::Add => 1,
+            Opcode::Mul => 2,
+            Opcode::Input => 3,
+            Opcode::Output => 4,
+            Opcode::JumpIfTrue => 5,
+            Opcode::JumpIfFalse => 6,
+            Opcode::LessThan => 7,
+            Opcode::Equals => 8,
+            Opcode::RelativeBaseOffset => 9,
+            Opcode::Terminate => 99,
+            Opcode::Custom(definition) => definition.number,
+        };
+    }
+
+    /// Whether executing the instruction consumes an input.
+    pub fn reads_input(&self) -> bool {
+        return match self {
+            Opcode::Input => true,
+            Opcode::Custom(definition) => definition.reads_input,
+            _ => false,
+        };
+    }
+
+    /// Looks up an opcode by its mnemonic, ignoring case.
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Opcode> {
+        return match mnemonic.to_uppercase().as_str() {
+            "ADD" => Some(Opcode::Add),
+            "MUL" => Some(Opcode::Mul),
+            "IN" => Some(Opcode::Input),
+            "OUT" => Some(Opcode::Output),
+            "JNZ" => Some(Opcode::JumpIfTrue),
+            "JZ" => Some(Opcode::JumpIfFalse),
+            "LT" => Some(Opcode::LessThan),
+            "EQ" => Some(Opcode::Equals),
+            "ARB" => Some(Opcode::RelativeBaseOffset),
+            "HLT" => Some(Opcode::Terminate),
+            _ => None,
+        };
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        return match self {
+            Opcode::Add => "ADD",
+            Opcode::Mul => "MUL",
+            Opcode::Input => "IN",
+            Opcode::Output => "OUT",
+            Opcode::JumpIfTrue => "JNZ",
+            Opcode::JumpIfFalse => "JZ",
+            Opcode::LessThan => "LT",
+            Opcode::Equals => "EQ",
+            Opcode::RelativeBaseOffset => "ARB",
+            Opcode::Terminate => "HLT",
+            Opcode::Custom(definition) => definition.name,
+        };
+    }
 }
 
-#[derive(Debug, PartialEq)]
-enum ParameterMode {
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ParameterMode {
     Position,
     Immediate,
     Relative,
 }
 
-pub fn parse_program_str(input_string: &str) -> Vec<i64> {
-    return input_string[..input_string.len() - 1] // get rid of \n character
-        .split(",")
-        .map(|s| s.parse::<i64>().expect("failed to convert input to i64"))
-        .collect();
+impl ParameterMode {
+    /// The digit encoding this mode in an instruction.
+    pub fn number(&self) -> i64 {
+        return match self {
+            ParameterMode::Position => 0,
+            ParameterMode::Immediate => 1,
+            ParameterMode::Relative => 2,
+        };
+    }
+}
+
+fn parse_parameter_mode(opcode_int: i64, parameter_id: usize) -> Result<ParameterMode, ErrorReason> {
+    let mode = opcode_int / 10i64.pow(parameter_id as u32 + 1) % 10;
+    return match mode {
+        0 => Ok(ParameterMode::Position),
+        1 => Ok(ParameterMode::Immediate),
+        2 => Ok(ParameterMode::Relative),
+        x => Err(ErrorReason::InvalidParameterMode { parameter_id, mode: x }),
+    };
 }
 
-fn parse_instruction(opcode_int: i64) -> (Opcode, ParameterMode, ParameterMode, ParameterMode) {
+fn parse_instruction(opcode_int: i64) -> Result<Instruction, ErrorReason> {
     let opcode = match opcode_int % 100 {
         1 => Opcode::Add,
         2 => Opcode::Mul,
@@ -41,152 +184,358 @@ fn parse_instruction(opcode_int: i64) -> (Opcode, ParameterMode, ParameterMode,
         8 => Opcode::Equals,
         9 => Opcode::RelativeBaseOffset,
         99 => Opcode::Terminate,
-        x => panic!("Invalid opcode found: {}!", x),
-    };
-    let pm_first = match opcode_int / 100 % 10 {
-        0 => ParameterMode::Position,
-        1 => ParameterMode::Immediate,
-        2 => ParameterMode::Relative,
-        x => panic!("Invalid parameter mode found: {}!", x),
-    };
-    let pm_second = match opcode_int / 1000 % 10 {
-        0 => ParameterMode::Position,
-        1 => ParameterMode::Immediate,
-        2 => ParameterMode::Relative,
-        x => panic!("Invalid parameter mode found: {}!", x),
+        _ => return Err(ErrorReason::InvalidOpcode(opcode_int)),
     };
-    let pm_third = match opcode_int / 10000 % 10 {
-        0 => ParameterMode::Position,
-        1 => ParameterMode::Immediate,
-        2 => ParameterMode::Relative,
-        x => panic!("Invalid parameter mode found: {}!", x),
-    };
-    return (opcode, pm_first, pm_second, pm_third);
+    let pm_first = parse_parameter_mode(opcode_int, 1)?;
+    let pm_second = parse_parameter_mode(opcode_int, 2)?;
+    let pm_third = parse_parameter_mode(opcode_int, 3)?;
+    return Ok((opcode, pm_first, pm_second, pm_third));
+}
+
+/// Encodes an instruction word from its opcode and the modes of its parameters, the inverse of `parse_instruction`.
+fn encode_instruction(opcode: Opcode, modes: impl IntoIterator<Item = ParameterMode>) -> i64 {
+    let mut word = opcode.number();
+    for (parameter_id, mode) in modes.into_iter().enumerate() {
+        word += mode.number() * 10i64.pow(parameter_id as u32 + 2);
+    }
+    return word;
 }
 
+/// A program together with its state. The memory backend defaults to `PagedMemory`,
+/// use `Program::with_memory` to pick another one.
 #[derive(Clone)]
-pub struct Program {
-    memory: HashMap<usize,i64>,
+pub struct Program<M: Memory = PagedMemory> {
+    memory: M,
     instruction_pointer: usize,
     relative_base: usize,
+    pending_inputs: VecDeque<i64>,
+    trace: Option<Trace>,
+    self_modifications: Option<SelfModificationDetector>,
+    watchpoints: Watchpoints,
+    profile: Option<Profile>,
+    undo_log: Option<UndoLog>,
+    decode_cache: DecodeCache,
+    overflow_policy: OverflowPolicy,
+    /// Values that don't fit into 64 bits, see `OverflowPolicy::Wide`. Memory holds their lower 64 bits.
+    wide_cells: HashMap<usize, i128>,
+    custom_opcodes: HashMap<i64, CustomOpcode<M>>,
+    /// Whether the last executed instruction was a custom one that yielded.
+    yielded: bool,
+}
+
+/// Why `Program::resume` returned.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RunState {
+    /// The next instruction is an input instruction, but no input was provided.
+    NeedsInput,
+    Output(i64),
+    /// A custom instruction returned `CustomAction::Yield`.
+    Yielded,
+    Halted,
 }
 
 impl Program {
     pub fn init<'a>(code: impl IntoIterator<Item=&'a i64>) -> Program {
+        let code: Vec<i64> = code.into_iter().cloned().collect();
+        return Program::with_memory(PagedMemory::from_code(&code));
+    }
+}
+
+impl<M: Memory> Program<M> {
+    /// Creates a program starting at address 0 of the given memory, e.g. `SparseMemory::from_code(&code)`.
+    pub fn with_memory(memory: M) -> Program<M> {
         return Program {
-            memory: code.into_iter().cloned().enumerate().collect(),
+            memory,
             instruction_pointer: 0,
             relative_base: 0,
+            pending_inputs: VecDeque::new(),
+            trace: None,
+            self_modifications: None,
+            watchpoints: Watchpoints::default(),
+            profile: None,
+            undo_log: None,
+            decode_cache: DecodeCache::default(),
+            overflow_policy: OverflowPolicy::default(),
+            wide_cells: HashMap::new(),
+            custom_opcodes: HashMap::new(),
+            yielded: false,
         };
     }
 
     pub fn set_memory(&mut self, address: usize, value: i64) {
-        self.memory.insert(address, value);
+        self.decode_cache.invalidate(address);
+        if !self.wide_cells.is_empty() {
+            self.wide_cells.remove(&address);
+        }
+        self.memory.write(address, value);
     }
 
     pub fn read_memory(&self, at: usize) -> i64 {
-        return *self.memory.get(&at).unwrap_or(&0);
+        return self.memory.read(at);
+    }
+
+    pub fn memory(&self) -> &M {
+        return &self.memory;
+    }
+
+    pub fn instruction_pointer(&self) -> usize {
+        return self.instruction_pointer;
+    }
+
+    pub fn relative_base(&self) -> usize {
+        return self.relative_base;
+    }
+
+    /// Starts recording every executed instruction. Clears the trace if tracing was already enabled.
+    pub fn enable_tracing(&mut self) {
+        self.trace = Some(Trace::default());
+    }
+
+    pub fn trace(&self) -> Option<&Trace> {
+        return self.trace.as_ref();
+    }
+
+    /// Returns the recorded trace and disables tracing.
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        return self.trace.take();
+    }
+
+    /// Starts recording executed and written addresses, to detect code that is modified by the program itself.
+    /// Clears everything recorded so far if detection was already enabled.
+    pub fn enable_self_modification_detection(&mut self) {
+        self.self_modifications = Some(SelfModificationDetector::default());
+    }
+
+    pub fn self_modifications(&self) -> Option<&SelfModificationDetector> {
+        return self.self_modifications.as_ref();
+    }
+
+    /// Starts counting executed instructions, coverage is measured relative to the current length of the memory.
+    /// Clears the profile if profiling was already enabled.
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Profile::new(self.memory.len()));
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        return self.profile.as_ref();
+    }
+
+    /// Calls the callback whenever an instruction reads or writes one of the given addresses, after the instruction was executed.
+    /// Returns an id to remove the watchpoint with `unwatch`.
+    pub fn watch(
+        &mut self,
+        addresses: impl RangeBounds<usize>,
+        kind: WatchKind,
+        callback: impl FnMut(&WatchEvent) + Send + 'static,
+    ) -> usize {
+        return self.watchpoints.add(addresses, kind, Arc::new(Mutex::new(callback)));
+    }
+
+    /// Returns false if there is no watchpoint with the given id.
+    pub fn unwatch(&mut self, id: usize) -> bool {
+        return self.watchpoints.remove(id);
     }
 
     pub fn will_terminate(&self) -> bool {
-        return self.next_opcode() == Opcode::Terminate;
+        return self.next_opcode() == Ok(Opcode::Terminate);
     }
 
     /// Runs the program until it terminates, using a fixed vector of inputs. Returns a vector of output data.
-    pub fn run(&mut self, mut input_values: Vec<i64>) -> Vec<i64> {
-        let mut program_output = Vec::new();
+    /// Inputs are taken from the end of the vector.
+    pub fn run(&mut self, mut input_values: Vec<i64>) -> Result<Vec<i64>, IntcodeError> {
+        let mut io = (|| input_values.pop(), Vec::new());
+        self.run_with(&mut io)?;
+        return Ok(io.1);
+    }
+
+    /// Runs the program until it terminates, taking inputs from and writing outputs to the given io.
+    /// Fails if an input is needed, but the io doesn't provide one anymore.
+    pub fn run_with(&mut self, io: &mut (impl InputSource + OutputSink)) -> Result<(), IntcodeError> {
         loop {
-            match self.next_opcode() {
-                Opcode::Input => {
-                    self.step(input_values.pop());
-                }
-                Opcode::Output => program_output.push(self.step(None).unwrap()),
-                Opcode::Terminate => return program_output,
-                _ => {
-                    self.step(None);
+            let input = match self.next_opcode()? {
+                opcode if opcode.reads_input() => match io.next_input() {
+                    Some(input) => Some(input),
+                    None => return Err(self.error(ErrorReason::MissingInput)),
+                },
+                Opcode::Terminate => return Ok(()),
+                _ => None,
+            };
+            if let Some(output) = self.step(input)? {
+                io.write_output(output);
+            }
+        }
+    }
+
+    /// Queues a value for the next input instruction executed by `resume`.
+    pub fn provide_input(&mut self, value: i64) {
+        self.pending_inputs.push_back(value);
+    }
+
+    /// Removes the values queued by `provide_input` that weren't consumed yet, in the order they were provided.
+    pub fn take_pending_inputs(&mut self) -> VecDeque<i64> {
+        return std::mem::take(&mut self.pending_inputs);
+    }
+
+    /// Runs the program until it produces an output, halts, or needs an input that wasn't provided via `provide_input`.
+    pub fn resume(&mut self) -> Result<RunState, IntcodeError> {
+        loop {
+            let input = match self.next_opcode()? {
+                opcode if opcode.reads_input() => match self.pending_inputs.front() {
+                    Some(input) => Some(*input),
+                    None => return Ok(RunState::NeedsInput),
+                },
+                Opcode::Terminate => return Ok(RunState::Halted),
+                _ => None,
+            };
+            let output = self.step(input)?;
+            if input.is_some() {
+                self.pending_inputs.pop_front();
+                if let Some(entry) = self.undo_log.as_mut().and_then(|log| log.entries.back_mut()) {
+                    entry.input_was_queued = true;
                 }
             }
+            if let Some(output) = output {
+                return Ok(RunState::Output(output));
+            }
+            if self.yielded {
+                return Ok(RunState::Yielded);
+            }
         }
     }
 
     /// runs the program input until it yields a single output or until it terminates.
-    pub fn run_until_output_or_terminate(&mut self) -> Option<i64> {
+    /// Fails when an input instruction is encountered.
+    pub fn run_until_output_or_terminate(&mut self) -> Result<Option<i64>, IntcodeError> {
         loop {
-            match self.next_opcode() {
+            match self.next_opcode()? {
                 Opcode::Output => return self.step(None),
-                Opcode::Terminate => return None,
-                Opcode::Input => panic!("Encountered Input instruction durign run_until_output_or_terminate!"),
-                _ => self.step(None),
+                Opcode::Terminate => return Ok(None),
+                opcode if opcode.reads_input() => return Err(self.error(ErrorReason::UnexpectedInput)),
+                _ => {
+                    if let Some(output) = self.step(None)? {
+                        return Ok(Some(output));
+                    }
+                }
             };
         }
     }
 
     /// Runs the program until after an input instruction was executed and takes exactly one input instruction.
-    /// Panics when Termination or Output happens during execution.
-    pub fn run_until_input(&mut self, input: i64) -> bool {
+    /// Returns false when the program terminates before that, fails when an output instruction is encountered.
+    pub fn run_until_input(&mut self, input: i64) -> Result<bool, IntcodeError> {
         loop {
-            match self.next_opcode() {
-                Opcode::Input => {
-                    self.step(Some(input));
-                    return true;
+            match self.next_opcode()? {
+                opcode if opcode.reads_input() => {
+                    self.step(Some(input))?;
+                    return Ok(true);
                 }
-                Opcode::Terminate => return false,
-                Opcode::Output => panic!("Encountered Output or Terminate instruction durign run_until_input!"),
-                _ => self.step(None),
+                Opcode::Terminate => return Ok(false),
+                Opcode::Output => return Err(self.error(ErrorReason::UnexpectedOutput)),
+                _ => self.step(None)?,
             };
         }
     }
 
+    /// All memory up to the highest address written so far.
     pub fn memory_as_vec(&self) -> Vec<i64> {
-        // get biggest key
-        let biggest_address = self.memory.keys().fold(0, |biggest_address_candidate, x| if x < &biggest_address_candidate {biggest_address_candidate} else {*x});
-        // craete vec with all zeroes of that size
-        let mut memory_as_vec = vec!(0; biggest_address as usize + 1);
-        // iterate over actual memory and set vec
-        for (address, value) in self.memory.iter() {
-            memory_as_vec[*address] = *value;
+        let mut memory_as_vec = vec!(0; self.memory.len());
+        for (address, value) in self.memory.cells() {
+            memory_as_vec[address] = value;
         }
         return memory_as_vec;
     }
 
     /// Executes exactly one instruction, may use a provided input if an input instruction is executed. May provide some output if an output instruction is executed.
-    pub fn step(&mut self, input: Option<i64>) -> Option<i64> {
-        let mut output = None;
-        match parse_instruction(self.memory[&self.instruction_pointer]) {
-            (Opcode::Add, pm1, pm2, pm3) => {
-                let first_operand = self.resolve_parameter_to_value(1, pm1);
-                let second_operand = self.resolve_parameter_to_value(2, pm2);
-                let result_address = self.resolve_parameter_to_result_address(3, pm3);
-                let result_value = first_operand + second_operand;
-                self.set_memory(result_address, result_value);
-                self.instruction_pointer += 4;
+    /// On failure, the program is left unchanged.
+    pub fn step(&mut self, input: Option<i64>) -> Result<Option<i64>, IntcodeError> {
+        self.yielded = false;
+        let trace_entry = match self.trace {
+            Some(_) => TraceEntry::before_step(self),
+            None => None,
+        };
+        let accesses = match self.self_modifications.is_some() || !self.watchpoints.is_empty() || self.undo_log.is_some() {
+            true => InstructionAccesses::inspect(self),
+            false => None,
+        };
+        let watch_events = match &accesses {
+            Some(accesses) if !self.watchpoints.is_empty() => self.watch_events(accesses),
+            _ => Vec::new(),
+        };
+        let undo_entry = match (&accesses, &self.undo_log) {
+            (Some(accesses), Some(_)) => Some(UndoEntry::before_step(self, accesses, input)),
+            _ => None,
+        };
+        let instruction_pointer = self.instruction_pointer;
+        let profiled_opcode = match self.profile {
+            Some(_) => self.next_opcode().ok(),
+            None => None,
+        };
+        let output = self.execute_instruction(input).map_err(|reason| self.error(reason))?;
+        if let (Some(entry), Some(log)) = (undo_entry, self.undo_log.as_mut()) {
+            log.push(entry);
+        }
+        if let (Some(opcode), Some(profile)) = (profiled_opcode, self.profile.as_mut()) {
+            profile.record(instruction_pointer, opcode);
+        }
+        if let (Some(accesses), Some(detector)) = (&accesses, self.self_modifications.as_mut()) {
+            detector.record(accesses);
+        }
+        for mut event in watch_events {
+            if event.access == Access::Write {
+                event.new_value = self.read_memory(event.address);
             }
-            (Opcode::Mul, pm1, pm2, pm3) => {
-                let first_operand = self.resolve_parameter_to_value(1, pm1);
-                let second_operand = self.resolve_parameter_to_value(2, pm2);
-                let result_address = self.resolve_parameter_to_result_address(3, pm3);
-                let result_value = first_operand * second_operand;
-                self.set_memory(result_address, result_value);
+            self.watchpoints.notify(&event);
+        }
+        if let Some(mut entry) = trace_entry {
+            entry.after_step(self, input, output);
+            self.trace.as_mut().unwrap().entries.push(entry);
+        }
+        return Ok(output);
+    }
+
+    /// Events for all watched addresses the instruction is going to access, with the values before executing it.
+    fn watch_events(&self, accesses: &InstructionAccesses) -> Vec<WatchEvent> {
+        let reads = accesses.reads.iter().map(|address| (*address, Access::Read));
+        let write = accesses.write.map(|address| (address, Access::Write));
+        return reads
+            .chain(write)
+            .filter(|(address, access)| self.watchpoints.is_watched(*address, *access))
+            .map(|(address, access)| WatchEvent {
+                instruction_pointer: accesses.instruction_pointer,
+                address,
+                access,
+                old_value: self.read_memory(address),
+                new_value: self.read_memory(address),
+            })
+            .collect();
+    }
+
+    fn execute_instruction(&mut self, input: Option<i64>) -> Result<Option<i64>, ErrorReason> {
+        let mut output = None;
+        match self.decode_instruction()? {
+            (opcode @ Opcode::Add, pm1, pm2, pm3) | (opcode @ Opcode::Mul, pm1, pm2, pm3) => {
+                let first_operand = self.resolve_parameter_to_wide_value(1, pm1)?;
+                let second_operand = self.resolve_parameter_to_wide_value(2, pm2)?;
+                let result_address = self.resolve_parameter_to_result_address(3, pm3)?;
+                let result_value = self.overflow_policy.evaluate(opcode, first_operand, second_operand)
+                    .ok_or(ErrorReason::ArithmeticOverflow { opcode, first_operand, second_operand })?;
+                self.set_wide_memory(result_address, result_value);
                 self.instruction_pointer += 4;
-                return None;
             }
             (Opcode::Input, pm1, _pm2, _pm3) => {
-                if input.is_some() {
-                    //println!("#{}: got value {} during input instruction", self.instruction_pointer, input.unwrap());
-                    let target_address = self.resolve_parameter_to_result_address(1, pm1);
-                    self.set_memory(target_address, input.unwrap());
-                    self.instruction_pointer += 2;
-                } else {
-                    panic!("Encountered input instruction without having any next given input.");
-                }
+                let input = input.ok_or(ErrorReason::MissingInput)?;
+                //println!("#{}: got value {} during input instruction", self.instruction_pointer, input);
+                let target_address = self.resolve_parameter_to_result_address(1, pm1)?;
+                self.set_memory(target_address, input);
+                self.instruction_pointer += 2;
             }
             (Opcode::Output, pm1, _pm2, _pm3) => {
-                output = Some(self.resolve_parameter_to_value(1, pm1));
+                output = Some(self.resolve_parameter_to_value(1, pm1)?);
                 self.instruction_pointer += 2;
             }
             (Opcode::JumpIfTrue, pm1, pm2, _pm3) => {
-                let condition = self.resolve_parameter_to_value(1, pm1);
-                let jump_target = self.resolve_parameter_to_jump_address(2, pm2);
+                let condition = self.resolve_parameter_to_wide_value(1, pm1)?;
+                let jump_target = self.resolve_parameter_to_jump_address(2, pm2)?;
                 if condition != 0 {
                     self.instruction_pointer = jump_target;
                 } else {
@@ -194,8 +543,8 @@ impl Program {
                 }
             }
             (Opcode::JumpIfFalse, pm1, pm2, _pm3) => {
-                let condition = self.resolve_parameter_to_value(1, pm1);
-                let jump_target = self.resolve_parameter_to_jump_address(2, pm2);
+                let condition = self.resolve_parameter_to_wide_value(1, pm1)?;
+                let jump_target = self.resolve_parameter_to_jump_address(2, pm2)?;
                 if condition == 0 {
                     self.instruction_pointer = jump_target;
                 } else {
@@ -203,60 +552,114 @@ impl Program {
                 }
             }
             (Opcode::LessThan, pm1, pm2, pm3) => {
-                let first_operand = self.resolve_parameter_to_value(1, pm1);
-                let second_operand = self.resolve_parameter_to_value(2, pm2);
-                let result_ptr = self.resolve_parameter_to_result_address(3, pm3);
+                let first_operand = self.resolve_parameter_to_wide_value(1, pm1)?;
+                let second_operand = self.resolve_parameter_to_wide_value(2, pm2)?;
+                let result_ptr = self.resolve_parameter_to_result_address(3, pm3)?;
                 self.set_memory(result_ptr, if first_operand < second_operand {1} else {0});
                 self.instruction_pointer += 4;
             }
             (Opcode::Equals, pm1, pm2, pm3) => {
-                let first_operand = self.resolve_parameter_to_value(1, pm1);
-                let second_operand = self.resolve_parameter_to_value(2, pm2);
-                let result_address = self.resolve_parameter_to_result_address(3, pm3);
+                let first_operand = self.resolve_parameter_to_wide_value(1, pm1)?;
+                let second_operand = self.resolve_parameter_to_wide_value(2, pm2)?;
+                let result_address = self.resolve_parameter_to_result_address(3, pm3)?;
                 self.set_memory(result_address, if first_operand == second_operand {1} else {0});
                 self.instruction_pointer += 4;
             }
             (Opcode::RelativeBaseOffset, pm1, _pm2, _pm3) => {
-                let offset = self.resolve_parameter_to_value(1, pm1);
-                self.relative_base = usize::try_from(self.relative_base as i64 + offset).expect("RelativeBaseOffset reduced program's relative base below zero.");
+                let offset = self.resolve_parameter_to_value(1, pm1)?;
+                let new_relative_base = self.offset_relative_base(offset)?;
+                self.relative_base = usize::try_from(new_relative_base).map_err(|_| ErrorReason::NegativeRelativeBase(new_relative_base))?;
                 self.instruction_pointer += 2;
             }
-            (Opcode::Terminate, _pm1, _pm2, _pm3) => ()
+            (Opcode::Terminate, _pm1, _pm2, _pm3) => (),
+            (Opcode::Custom(definition), pm1, pm2, pm3) => {
+                output = self.execute_custom(definition, [pm1, pm2, pm3], input)?;
+            }
+        }
+        return Ok(output);
+    }
+
+    pub fn next_opcode(&self) -> Result<Opcode, IntcodeError> {
+        return self.peek_instruction()
+            .map(|instruction| instruction.0)
+            .map_err(|reason| self.error(reason));
+    }
+
+    /// Decodes the instruction at the instruction pointer, including custom opcodes, using the decode cache if possible.
+    fn peek_instruction(&self) -> Result<Instruction, ErrorReason> {
+        if let Some(instruction) = self.decode_cache.get(self.instruction_pointer) {
+            return Ok(instruction);
         }
-        return output;
+        let word = self.read_memory(self.instruction_pointer);
+        return match parse_instruction(word) {
+            Err(ErrorReason::InvalidOpcode(_)) if !self.custom_opcodes.is_empty() => {
+                self.decode_custom(word).unwrap_or(Err(ErrorReason::InvalidOpcode(word)))
+            }
+            result => result,
+        };
     }
 
-    pub fn next_opcode(&self) -> Opcode {
-        return parse_instruction(self.memory[&self.instruction_pointer]).0;
+    /// Like `peek_instruction`, but adds the instruction to the decode cache.
+    fn decode_instruction(&mut self) -> Result<Instruction, ErrorReason> {
+        let instruction = self.peek_instruction()?;
+        self.decode_cache.insert(self.instruction_pointer, instruction);
+        return Ok(instruction);
+    }
+
+    /// Wraps a failure reason into an error describing the instruction at the current instruction pointer.
+    fn error(&self, reason: ErrorReason) -> IntcodeError {
+        return IntcodeError {
+            instruction_pointer: self.instruction_pointer,
+            opcode: self.read_memory(self.instruction_pointer),
+            reason,
+        };
     }
 
     /// Resolves a parameter into the value it describes, depending on its parameter mode.
-    fn resolve_parameter_to_value(&self, parameter_id: usize, parameter_mode: ParameterMode) -> i64 {
+    /// Fails if the value doesn't fit into 64 bits.
+    fn resolve_parameter_to_value(&self, parameter_id: usize, parameter_mode: ParameterMode) -> Result<i64, ErrorReason> {
+        let value = self.resolve_parameter_to_wide_value(parameter_id, parameter_mode)?;
+        return i64::try_from(value).map_err(|_| ErrorReason::WideValue(value));
+    }
+
+    fn resolve_parameter_to_wide_value(&self, parameter_id: usize, parameter_mode: ParameterMode) -> Result<i128, ErrorReason> {
         match parameter_mode {
-            ParameterMode::Immediate => return self.read_memory(self.instruction_pointer + parameter_id),
-            ParameterMode::Position => {
-                let address = usize::try_from(self.read_memory(self.instruction_pointer + parameter_id)).expect("Parameter in position mode tried to access a negative address.");
-                return self.read_memory(address);
-            }
-            ParameterMode::Relative => {
-                let address = usize::try_from(self.read_memory(self.instruction_pointer + parameter_id) + self.relative_base as i64).expect("Relative mode address invalid.");
-                return self.read_memory(address);
+            ParameterMode::Immediate => return Ok(self.read_wide_memory(self.instruction_pointer + parameter_id)),
+            ParameterMode::Position | ParameterMode::Relative => {
+                let address = self.resolve_parameter_to_address(parameter_id, parameter_mode)?;
+                return Ok(self.read_wide_memory(address));
             }
         }
     }
 
-    fn resolve_parameter_to_jump_address(&self, parameter_id: usize, parameter_mode: ParameterMode) -> usize {
-        return usize::try_from(self.resolve_parameter_to_value(parameter_id, parameter_mode)).expect("invalid address as target of a jump instruction.");
+    fn resolve_parameter_to_jump_address(&self, parameter_id: usize, parameter_mode: ParameterMode) -> Result<usize, ErrorReason> {
+        let jump_target = self.resolve_parameter_to_value(parameter_id, parameter_mode)?;
+        return usize::try_from(jump_target).map_err(|_| ErrorReason::InvalidJumpTarget(jump_target));
     }
 
     /// Resolves a parameter into the address it describes, depending on its parameter mode.
-    fn resolve_parameter_to_result_address(&self, parameter_id: usize, parameter_mode: ParameterMode) -> usize {
+    fn resolve_parameter_to_result_address(&self, parameter_id: usize, parameter_mode: ParameterMode) -> Result<usize, ErrorReason> {
         return match parameter_mode {
-            ParameterMode::Position => usize::try_from(self.read_memory(self.instruction_pointer + parameter_id)).expect("A parameter that is interpreted as an address is negative."),
-            ParameterMode::Relative => usize::try_from(self.relative_base as i64 + self.read_memory(self.instruction_pointer + parameter_id)).expect("A parameter that is interpreted as an address is negative."),
-            ParameterMode::Immediate => panic!("Immediate mode is invalid ParameterMode for result addresses."),
+            ParameterMode::Immediate => Err(ErrorReason::ImmediateModeResult { parameter_id }),
+            _ => self.resolve_parameter_to_address(parameter_id, parameter_mode),
         }
     }
+
+    fn offset_relative_base(&self, offset: i64) -> Result<i64, ErrorReason> {
+        return i64::try_from(self.relative_base)
+            .ok()
+            .and_then(|relative_base| relative_base.checked_add(offset))
+            .ok_or(ErrorReason::RelativeBaseOverflow { relative_base: self.relative_base, offset });
+    }
+
+    fn resolve_parameter_to_address(&self, parameter_id: usize, parameter_mode: ParameterMode) -> Result<usize, ErrorReason> {
+        let parameter = self.resolve_parameter_to_value(parameter_id, ParameterMode::Immediate)?;
+        let address = match parameter_mode {
+            ParameterMode::Relative => self.offset_relative_base(parameter)?,
+            _ => parameter,
+        };
+        return usize::try_from(address).map_err(|_| ErrorReason::NegativeAddress { parameter_id, address });
+    }
 }
 
 #[cfg(test)]
@@ -266,13 +669,28 @@ mod test {
     fn program_with_less_than_in_immediate_mode() {
         for input in 0..8 {
             let mut program = Program::init(&[1107, input, 8, 1, 4, 1, 99]);
-            program.run(Vec::new());
-            assert_eq!(program.memory[&1], 1);
+            program.run(Vec::new()).unwrap();
+            assert_eq!(program.read_memory(1), 1);
         }
         for input in 8..12 {
             let mut program = Program::init(&[1107, input, 8, 1, 4, 1, 99]);
-            program.run(Vec::new());
-            assert_eq!(program.memory[&1], 0);
+            program.run(Vec::new()).unwrap();
+            assert_eq!(program.read_memory(1), 0);
+        }
+    }
+
+    #[test]
+    fn program_with_less_than_in_assembly() {
+        let source = "
+                LT #input, #8, [result]
+                OUT [result]
+                HLT
+        result: .data -1
+        ";
+        for (input, expected) in [(7, 1), (8, 0)].iter() {
+            let code = assembler::assemble(&source.replace("input", &input.to_string())).unwrap();
+            let mut program = Program::init(&code);
+            assert_eq!(program.run(Vec::new()).unwrap(), [*expected]);
         }
     }
 
@@ -280,17 +698,17 @@ mod test {
     fn program_with_equals_in_immediate_mode() {
         for input in 0..8 {
             let mut program = Program::init(&[1108, input, 8, 1, 4, 1, 99]);
-            program.run(Vec::new());
-            assert_eq!(program.memory[&1], 0);
+            program.run(Vec::new()).unwrap();
+            assert_eq!(program.read_memory(1), 0);
         }
         let input = 8;
         let mut program = Program::init(&[1108, input, 8, 1, 4, 1, 99]);
-        program.run(Vec::new());
-        assert_eq!(program.memory[&1], 1);
+        program.run(Vec::new()).unwrap();
+        assert_eq!(program.read_memory(1), 1);
         for input in 9..12 {
             let mut program = Program::init(&[1108, input, 8, 1, 4, 1, 99]);
-            program.run(Vec::new());
-            assert_eq!(program.memory[&1], 0);
+            program.run(Vec::new()).unwrap();
+            assert_eq!(program.read_memory(1), 0);
         }
     }
 
@@ -298,13 +716,13 @@ mod test {
     fn program_with_less_than_in_position_mode() {
         for input in 0..8 {
             let mut program = Program::init(&[7, 7, 8, 7, 4, 7, 99, input, 8]);
-            program.run(Vec::new());
-            assert_eq!(program.memory[&7], 1);
+            program.run(Vec::new()).unwrap();
+            assert_eq!(program.read_memory(7), 1);
         }
         for input in 8..12 {
             let mut program = Program::init(&[7, 7, 8, 7, 4, 7, 99, input, 8]);
-            program.run(Vec::new());
-            assert_eq!(program.memory[&7], 0);
+            program.run(Vec::new()).unwrap();
+            assert_eq!(program.read_memory(7), 0);
         }
     }
 
@@ -312,17 +730,17 @@ mod test {
     fn program_with_equals_in_position_mode() {
         for input in 0..8 {
             let mut program = Program::init(&[8, 7, 8, 7, 4, 7, 99, input, 8]);
-            program.run(Vec::new());
-            assert_eq!(program.memory[&7], 0);
+            program.run(Vec::new()).unwrap();
+            assert_eq!(program.read_memory(7), 0);
         }
         let input = 8;
         let mut program = Program::init(&[8, 7, 8, 7, 4, 7, 99, input, 8]);
-        program.run(Vec::new());
-        assert_eq!(program.memory[&7], 1);
+        program.run(Vec::new()).unwrap();
+        assert_eq!(program.read_memory(7), 1);
         for input in 9..12 {
             let mut program = Program::init(&[8, 7, 8, 7, 4, 7, 99, input, 8]);
-            program.run(Vec::new());
-            assert_eq!(program.memory[&7], 0);
+            program.run(Vec::new()).unwrap();
+            assert_eq!(program.read_memory(7), 0);
         }
     }
 
@@ -330,34 +748,34 @@ mod test {
     fn program_with_jump_in_position_mode() {
         let input = 0;
         let mut program = Program::init(&[6, 10, 13, 1, 11, 12, 11, 4, 11, 99, input, 0, 1, 9]);
-        program.run(Vec::new());
-        assert_eq!(program.memory[&11], 0);
+        program.run(Vec::new()).unwrap();
+        assert_eq!(program.read_memory(11), 0);
         let input = 3;
         let mut program = Program::init(&[6, 10, 13, 1, 11, 12, 11, 4, 11, 99, input, 0, 1, 9]);
-        program.run(Vec::new());
-        assert_eq!(program.memory[&11], 1);
+        program.run(Vec::new()).unwrap();
+        assert_eq!(program.read_memory(11), 1);
     }
 
     #[test]
     fn program_with_negative_immediate_values() {
         let mut program = Program::init(&[1101, 100, -1, 4, 0]);
-        let instruction = parse_instruction(program.memory[&0]);
+        let instruction = parse_instruction(program.read_memory(0)).unwrap();
         assert_eq!(instruction.0, Opcode::Add);
         assert_eq!(instruction.1, ParameterMode::Immediate);
         assert_eq!(instruction.2, ParameterMode::Immediate);
         assert_eq!(instruction.3, ParameterMode::Position);
-        program.run(Vec::new());
+        program.run(Vec::new()).unwrap();
         assert_eq!(program.memory_as_vec(), [1101, 100, -1, 4, 99]);
     }
 
     #[test]
     fn opcode_add() {
         let mut program = Program::init(&[1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]);
-        program.step(None);
+        program.step(None).unwrap();
         assert_eq!(program.instruction_pointer, 4);
         assert_eq!(program.memory_as_vec(), [1, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50]);
         let mut program = Program::init(&[1, 0, 0, 0, 99]);
-        program.step(None);
+        program.step(None).unwrap();
         assert_eq!(program.instruction_pointer, 4);
         assert_eq!(program.memory_as_vec(), [2, 0, 0, 0, 99]);
     }
@@ -365,25 +783,25 @@ mod test {
     #[test]
     fn opcode_output_supports_immediate_mode() {
         let mut program = Program::init(&[104, 0, 99]);
-        program.run(Vec::new()); // should output 0, but testing that seems too complicated
+        program.run(Vec::new()).unwrap(); // should output 0, but testing that seems too complicated
     }
 
     #[test]
     fn opcode_mul() {
         let mut program = Program::init(&[1, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50]);
         program.instruction_pointer = 4;
-        program.step(None);
+        program.step(None).unwrap();
         assert_eq!(program.instruction_pointer, 8);
         assert_eq!(
             program.memory_as_vec(),
             [3500, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50]
         );
         let mut program = Program::init(&[2, 3, 0, 3, 99]);
-        program.step(None);
+        program.step(None).unwrap();
         assert_eq!(program.instruction_pointer, 4);
         assert_eq!(program.memory_as_vec(), [2, 3, 0, 6, 99]);
         let mut program = Program::init(&[2, 4, 4, 5, 99, 0]);
-        program.step(None);
+        program.step(None).unwrap();
         assert_eq!(program.instruction_pointer, 4);
         assert_eq!(program.memory_as_vec(), [2, 4, 4, 5, 99, 9801]);
     }
@@ -398,7 +816,7 @@ mod test {
     fn opcode_relative_base_increase() {
         let mut program = Program::init(&[109, 19]);
         program.relative_base = 2000;
-        program.step(None);
+        program.step(None).unwrap();
         assert_eq!(program.instruction_pointer, 2);
         assert_eq!(program.relative_base, 2019);
     }
@@ -407,126 +825,212 @@ mod test {
     fn opcode_relative_base_decrease() {
         let mut program = Program::init(&[109, -200]);
         program.relative_base = 2000;
-        program.step(None);
+        program.step(None).unwrap();
         assert_eq!(program.instruction_pointer, 2);
         assert_eq!(program.relative_base, 1800);
     }
 
     #[test]
-    #[should_panic]
-    fn opcode_relative_base_panics() {
+    fn opcode_relative_base_fails_below_zero() {
         let mut program = Program::init(&[109, -200]);
         program.relative_base = 199;
-        program.step(None);
+        let error = program.step(None).unwrap_err();
+        assert_eq!(error.instruction_pointer, 0);
+        assert_eq!(error.opcode, 109);
+        assert_eq!(error.reason, ErrorReason::NegativeRelativeBase(-1));
+        assert_eq!(program.relative_base, 199);
+    }
+
+    #[test]
+    fn opcode_relative_base_fails_on_overflow() {
+        let mut program = Program::init(&[109, i64::MAX, 109, 1, 99]);
+        let error = program.run(Vec::new()).unwrap_err();
+        assert_eq!(error.instruction_pointer, 2);
+        assert_eq!(error.reason, ErrorReason::RelativeBaseOverflow { relative_base: i64::MAX as usize, offset: 1 });
+        assert_eq!(program.relative_base, i64::MAX as usize);
+    }
+
+    #[test]
+    fn relative_address_overflow_fails() {
+        let error = Program::init(&[109, i64::MAX, 204, 1, 99]).run(Vec::new()).unwrap_err();
+        assert_eq!(error.instruction_pointer, 2);
+        assert_eq!(error.reason, ErrorReason::RelativeBaseOverflow { relative_base: i64::MAX as usize, offset: 1 });
+    }
+
+    #[test]
+    fn resume_stops_at_io() {
+        // outputs the double of every input, until it receives 0
+        let code = assembler::assemble("
+            loop:   IN [x]
+                    JZ [x], #end
+                    MUL [x], #2, [x]
+                    OUT [x]
+                    JNZ #1, #loop
+            end:    HLT
+            x:      .data 0
+        ").unwrap();
+        let mut program = Program::init(&code);
+        assert_eq!(program.resume(), Ok(RunState::NeedsInput));
+        assert_eq!(program.resume(), Ok(RunState::NeedsInput));
+        program.provide_input(4);
+        program.provide_input(-1);
+        assert_eq!(program.resume(), Ok(RunState::Output(8)));
+        assert_eq!(program.resume(), Ok(RunState::Output(-2)));
+        assert_eq!(program.resume(), Ok(RunState::NeedsInput));
+        program.provide_input(0);
+        assert_eq!(program.resume(), Ok(RunState::Halted));
+        assert_eq!(program.resume(), Ok(RunState::Halted));
+    }
+
+    #[test]
+    fn resume_keeps_input_on_failure() {
+        let mut program = Program::init(&[103, 0, 99]);
+        program.provide_input(1);
+        assert_eq!(program.resume().unwrap_err().reason, ErrorReason::ImmediateModeResult { parameter_id: 1 });
+        program.set_memory(0, 3);
+        assert_eq!(program.resume(), Ok(RunState::Halted));
+        assert_eq!(program.read_memory(0), 1);
     }
 
     #[test]
     fn mini_program() {
         let mut program = Program::init(&[1, 1, 1, 4, 99, 5, 6, 0, 99]);
-        program.run(Vec::new());
+        program.run(Vec::new()).unwrap();
         assert_eq!(program.memory_as_vec(), [30, 1, 1, 4, 2, 5, 6, 0, 99]);
     }
 
     #[test]
-    #[should_panic]
-    fn parse_instruction_panics() {
-        let instruction = 28;
-        parse_instruction(instruction);
+    fn parse_instruction_fails() {
+        assert_eq!(parse_instruction(28), Err(ErrorReason::InvalidOpcode(28)));
+        assert_eq!(parse_instruction(-1), Err(ErrorReason::InvalidOpcode(-1)));
+        assert_eq!(
+            parse_instruction(3201),
+            Err(ErrorReason::InvalidParameterMode { parameter_id: 2, mode: 3 })
+        );
     }
 
     #[test]
     fn parse_instruction_add() {
         let instruction = 1;
-        assert_eq!(parse_instruction(instruction).0, Opcode::Add);
-        assert_eq!(parse_instruction(instruction).1, ParameterMode::Position);
-        assert_eq!(parse_instruction(instruction).2, ParameterMode::Position);
-        assert_eq!(parse_instruction(instruction).3, ParameterMode::Position);
+        assert_eq!(parse_instruction(instruction).unwrap().0, Opcode::Add);
+        assert_eq!(parse_instruction(instruction).unwrap().1, ParameterMode::Position);
+        assert_eq!(parse_instruction(instruction).unwrap().2, ParameterMode::Position);
+        assert_eq!(parse_instruction(instruction).unwrap().3, ParameterMode::Position);
     }
 
     #[test]
     fn parse_instruction_mul() {
         let instruction = 2;
-        assert_eq!(parse_instruction(instruction).0, Opcode::Mul);
-        assert_eq!(parse_instruction(instruction).1, ParameterMode::Position);
-        assert_eq!(parse_instruction(instruction).2, ParameterMode::Position);
-        assert_eq!(parse_instruction(instruction).3, ParameterMode::Position);
+        assert_eq!(parse_instruction(instruction).unwrap().0, Opcode::Mul);
+        assert_eq!(parse_instruction(instruction).unwrap().1, ParameterMode::Position);
+        assert_eq!(parse_instruction(instruction).unwrap().2, ParameterMode::Position);
+        assert_eq!(parse_instruction(instruction).unwrap().3, ParameterMode::Position);
     }
 
     #[test]
     fn parse_instruction_input() {
         let instruction = 3;
-        assert_eq!(parse_instruction(instruction).0, Opcode::Input);
-        assert_eq!(parse_instruction(instruction).1, ParameterMode::Position);
+        assert_eq!(parse_instruction(instruction).unwrap().0, Opcode::Input);
+        assert_eq!(parse_instruction(instruction).unwrap().1, ParameterMode::Position);
     }
 
     #[test]
     fn parse_instruction_output() {
         let instruction = 4;
-        assert_eq!(parse_instruction(instruction).0, Opcode::Output);
-        assert_eq!(parse_instruction(instruction).1, ParameterMode::Position);
+        assert_eq!(parse_instruction(instruction).unwrap().0, Opcode::Output);
+        assert_eq!(parse_instruction(instruction).unwrap().1, ParameterMode::Position);
     }
 
     #[test]
     fn parse_instruction_jump_if_true() {
         let instruction = 5;
-        assert_eq!(parse_instruction(instruction).0, Opcode::JumpIfTrue);
-        assert_eq!(parse_instruction(instruction).1, ParameterMode::Position);
-        assert_eq!(parse_instruction(instruction).2, ParameterMode::Position);
+        assert_eq!(parse_instruction(instruction).unwrap().0, Opcode::JumpIfTrue);
+        assert_eq!(parse_instruction(instruction).unwrap().1, ParameterMode::Position);
+        assert_eq!(parse_instruction(instruction).unwrap().2, ParameterMode::Position);
         let instruction = 1105;
-        assert_eq!(parse_instruction(instruction).0, Opcode::JumpIfTrue);
+        assert_eq!(parse_instruction(instruction).unwrap().0, Opcode::JumpIfTrue);
         // parse_instruction doesn't check whether given mode is valid
-        assert_eq!(parse_instruction(instruction).1, ParameterMode::Immediate);
-        assert_eq!(parse_instruction(instruction).2, ParameterMode::Immediate);
+        assert_eq!(parse_instruction(instruction).unwrap().1, ParameterMode::Immediate);
+        assert_eq!(parse_instruction(instruction).unwrap().2, ParameterMode::Immediate);
     }
 
     #[test]
     fn parse_instruction_jump_if_false() {
         let instruction = 6;
-        assert_eq!(parse_instruction(instruction).0, Opcode::JumpIfFalse);
+        assert_eq!(parse_instruction(instruction).unwrap().0, Opcode::JumpIfFalse);
     }
 
     #[test]
     fn parse_instruction_less_than() {
         let instruction = 107;
-        assert_eq!(parse_instruction(instruction).0, Opcode::LessThan);
+        assert_eq!(parse_instruction(instruction).unwrap().0, Opcode::LessThan);
     }
 
     #[test]
     fn parse_instruction_equals() {
         let instruction = 8;
-        assert_eq!(parse_instruction(instruction).0, Opcode::Equals);
+        assert_eq!(parse_instruction(instruction).unwrap().0, Opcode::Equals);
     }
 
     #[test]
     fn parse_instruction_omit_zero() {
-        assert_eq!(parse_instruction(1002).0, Opcode::Mul);
-        assert_eq!(parse_instruction(1002).1, ParameterMode::Position);
-        assert_eq!(parse_instruction(1002).2, ParameterMode::Immediate);
-        assert_eq!(parse_instruction(1002).3, ParameterMode::Position);
+        assert_eq!(parse_instruction(1002).unwrap().0, Opcode::Mul);
+        assert_eq!(parse_instruction(1002).unwrap().1, ParameterMode::Position);
+        assert_eq!(parse_instruction(1002).unwrap().2, ParameterMode::Immediate);
+        assert_eq!(parse_instruction(1002).unwrap().3, ParameterMode::Position);
     }
 
     #[test]
     fn relative_base_output_instruction() {
         let code = [109,19,204,-8,99,0,0,0,0,0,0,42];
         let mut program = Program::init(&code);
-        let output = program.run(Vec::new());
+        let output = program.run(Vec::new()).unwrap();
         assert_eq!(output[0], 42);
     }
 
     #[test]
-    #[should_panic]
     fn using_negative_memory_address() {
-        let code = [104,-5,99];
+        let code = [4,-5,99];
         let mut program = Program::init(&code);
-        let output = program.run(Vec::new());
-        assert_eq!(output[0], 0);
+        let error = program.run(Vec::new()).unwrap_err();
+        assert_eq!(error, IntcodeError {
+            instruction_pointer: 0,
+            opcode: 4,
+            reason: ErrorReason::NegativeAddress { parameter_id: 1, address: -5 },
+        });
+    }
+
+    #[test]
+    fn missing_input_fails() {
+        let mut program = Program::init(&[1101, 1, 1, 5, 3, 0, 99]);
+        let error = program.run(Vec::new()).unwrap_err();
+        assert_eq!(error.instruction_pointer, 4);
+        assert_eq!(error.reason, ErrorReason::MissingInput);
+    }
+
+    #[test]
+    fn immediate_mode_result_fails() {
+        let mut program = Program::init(&[11101, 1, 1, 5, 99]);
+        let error = program.step(None).unwrap_err();
+        assert_eq!(error.reason, ErrorReason::ImmediateModeResult { parameter_id: 3 });
+        assert_eq!(program.memory_as_vec(), [11101, 1, 1, 5, 99]);
+    }
+
+    #[test]
+    fn run_until_output_fails_on_input() {
+        let mut program = Program::init(&[3, 0, 99]);
+        let error = program.run_until_output_or_terminate().unwrap_err();
+        assert_eq!(error.reason, ErrorReason::UnexpectedInput);
+        let mut program = Program::init(&[104, 0, 99]);
+        let error = program.run_until_input(1).unwrap_err();
+        assert_eq!(error.reason, ErrorReason::UnexpectedOutput);
     }
 
     #[test]
     fn using_initialized_memory() {
         let code = [4,5,99];
         let mut program = Program::init(&code);
-        let output = program.run(Vec::new());
+        let output = program.run(Vec::new()).unwrap();
         assert_eq!(output[0], 0);
     }
 
@@ -534,15 +1038,42 @@ mod test {
     fn test_program_relative_base_duplicates_itself() {
         let code = [109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99];
         let mut program = Program::init(&code);
-        let output = program.run(Vec::new());
+        let output = program.run(Vec::new()).unwrap();
+        assert_eq!(output, code);
+        let mut program = Program::with_memory(SparseMemory::from_code(&code));
+        let output = program.run(Vec::new()).unwrap();
         assert_eq!(output, code);
     }
 
+    #[test]
+    fn self_modifying_code_invalidates_decoded_instructions() {
+        // the second time around, the first instruction outputs in position mode
+        let source = "
+            start:  OUT #5
+                    JNZ [done], #end
+                    ADD [start], #-100, [start]
+                    ADD #1, #0, [done]
+                    JNZ #1, #start
+            end:    HLT
+            done:   .data 0
+        ";
+        let mut program = Program::init(&assembler::assemble(source).unwrap());
+        assert_eq!(program.run(Vec::new()).unwrap(), [5, 1001]);
+    }
+
+    #[test]
+    fn sparse_memory_with_huge_address() {
+        // stores the input at address 2^40 and outputs it again
+        let mut program = Program::with_memory(SparseMemory::from_code(&[109, 1 << 40, 203, 0, 204, 0, 99]));
+        assert_eq!(program.run(vec![-5]).unwrap(), [-5]);
+        assert_eq!(program.read_memory(1 << 40), -5);
+    }
+
     #[test]
     fn test_handle_large_number_output() {
         let code = [104,1125899906842624,99];
         let mut program = Program::init(&code);
-        let output = program.run(Vec::new());
+        let output = program.run(Vec::new()).unwrap();
         assert_eq!(output[0], 1125899906842624);
     }
 
@@ -550,7 +1081,7 @@ mod test {
     fn test_handle_large_number_multiplication() {
         let code = [1102,34915192,34915192,7,4,7,99,0];
         let mut program = Program::init(&code);
-        let output = program.run(Vec::new());
+        let output = program.run(Vec::new()).unwrap();
         println!("output: {:?}", output[0]);
         assert_eq!(output[0], 1219070632396864);
     }
@@ -0,0 +1,203 @@
+//! Storage backends for a program's memory.
+use std::collections::{BTreeMap, HashMap};
+
+/// Memory of a program. Cells that were never written read as 0.
+pub trait Memory: Clone + Default {
+    fn read(&self, address: usize) -> i64;
+
+    fn write(&mut self, address: usize, value: i64);
+
+    /// One past the highest address that was written so far.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    /// All cells that may hold a value other than 0, in ascending order of their addresses.
+    fn cells(&self) -> Vec<(usize, i64)>;
+
+    fn from_code(code: &[i64]) -> Self {
+        let mut memory = Self::default();
+        for (address, value) in code.iter().enumerate() {
+            memory.write(address, *value);
+        }
+        return memory;
+    }
+}
+
+const PAGE_SIZE: usize = 1024;
+/// Pages below this id are kept in a table indexed by their id, which grows up to the highest one used.
+const DENSE_PAGES: usize = 1 << 12;
+
+type Page = Box<[i64; PAGE_SIZE]>;
+
+fn new_page(offset: usize, value: i64) -> Page {
+    let mut page = Box::new([0; PAGE_SIZE]);
+    page[offset] = value;
+    return page;
+}
+
+/// Dense memory, split into pages which are allocated when they're first written to.
+/// Reads and writes are cheap for the first few million addresses. Pages beyond those are kept in a map,
+/// programs that spread out across huge addresses should use `SparseMemory` instead.
+#[derive(Clone, Default)]
+pub struct PagedMemory {
+    pages: Vec<Option<Page>>,
+    far_pages: BTreeMap<usize, Page>,
+    len: usize,
+}
+
+impl Memory for PagedMemory {
+    fn read(&self, address: usize) -> i64 {
+        let page_id = address / PAGE_SIZE;
+        let page = match page_id {
+            page_id if page_id < DENSE_PAGES => self.pages.get(page_id).and_then(|page| page.as_ref()),
+            page_id => self.far_pages.get(&page_id),
+        };
+        return page.map_or(0, |page| page[address % PAGE_SIZE]);
+    }
+
+    fn write(&mut self, address: usize, value: i64) {
+        self.len = self.len.max(address.saturating_add(1));
+        let page_id = address / PAGE_SIZE;
+        if page_id >= DENSE_PAGES {
+            match self.far_pages.get_mut(&page_id) {
+                Some(page) => page[address % PAGE_SIZE] = value,
+                None if value == 0 => (),
+                None => {
+                    self.far_pages.insert(page_id, new_page(address % PAGE_SIZE, value));
+                }
+            }
+            return;
+        }
+        if page_id >= self.pages.len() {
+            if value == 0 {
+                return;
+            }
+            self.pages.resize_with(page_id + 1, || None);
+        }
+        match &mut self.pages[page_id] {
+            Some(page) => page[address % PAGE_SIZE] = value,
+            None if value == 0 => (),
+            page => *page = Some(new_page(address % PAGE_SIZE, value)),
+        }
+    }
+
+    fn len(&self) -> usize {
+        return self.len;
+    }
+
+    fn cells(&self) -> Vec<(usize, i64)> {
+        let mut cells = Vec::new();
+        let dense_pages = self.pages.iter().enumerate().filter_map(|(page_id, page)| Some((page_id, page.as_ref()?)));
+        for (page_id, page) in dense_pages.chain(self.far_pages.iter().map(|(page_id, page)| (*page_id, page))) {
+            let page_start = page_id * PAGE_SIZE;
+            // a saturated length may include the last address
+            let page_last = match self.len {
+                usize::MAX => page_start + (PAGE_SIZE - 1),
+                len => (len - 1).min(page_start + (PAGE_SIZE - 1)),
+            };
+            for address in page_start..=page_last {
+                cells.push((address, page[address - page_start]));
+            }
+        }
+        return cells;
+    }
+}
+
+/// Memory storing every written cell in a hash map. Slower than `PagedMemory`, but handles arbitrary addresses.
+#[derive(Clone, Default)]
+pub struct SparseMemory {
+    cells: HashMap<usize, i64>,
+    len: usize,
+}
+
+impl Memory for SparseMemory {
+    fn read(&self, address: usize) -> i64 {
+        return *self.cells.get(&address).unwrap_or(&0);
+    }
+
+    fn write(&mut self, address: usize, value: i64) {
+        self.cells.insert(address, value);
+        self.len = self.len.max(address.saturating_add(1));
+    }
+
+    fn len(&self) -> usize {
+        return self.len;
+    }
+
+    fn cells(&self) -> Vec<(usize, i64)> {
+        let mut cells: Vec<(usize, i64)> = self.cells.iter().map(|(address, value)| (*address, *value)).collect();
+        cells.sort();
+        return cells;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read_and_write<M: Memory>() {
+        let mut memory = M::from_code(&[1, 2, 3]);
+        assert_eq!(memory.len(), 3);
+        assert_eq!(memory.read(1), 2);
+        assert_eq!(memory.read(3), 0);
+        assert_eq!(memory.read(1_000_000), 0);
+        memory.write(2 * PAGE_SIZE + 5, -7);
+        assert_eq!(memory.read(2 * PAGE_SIZE + 5), -7);
+        assert_eq!(memory.len(), 2 * PAGE_SIZE + 6);
+        memory.write(5 * PAGE_SIZE, 0);
+        assert_eq!(memory.len(), 5 * PAGE_SIZE + 1);
+        let non_zero_cells: Vec<(usize, i64)> = memory.cells().into_iter().filter(|cell| cell.1 != 0).collect();
+        assert_eq!(non_zero_cells, [(0, 1), (1, 2), (2, 3), (2 * PAGE_SIZE + 5, -7)]);
+    }
+
+    #[test]
+    fn paged_memory() {
+        read_and_write::<PagedMemory>();
+    }
+
+    #[test]
+    fn sparse_memory() {
+        read_and_write::<SparseMemory>();
+    }
+
+    #[test]
+    fn paged_memory_allocates_pages_on_demand() {
+        let mut memory = PagedMemory::from_code(&[1, 2, 3]);
+        memory.write(3 * PAGE_SIZE, 0);
+        assert_eq!(memory.pages.len(), 1);
+        memory.write(3 * PAGE_SIZE + 1, 4);
+        assert_eq!(memory.pages.len(), 4);
+        assert_eq!(memory.pages.iter().filter(|page| page.is_some()).count(), 2);
+        assert_eq!(memory.cells().len(), PAGE_SIZE + 2);
+    }
+
+    #[test]
+    fn paged_memory_handles_huge_addresses() {
+        let mut memory = PagedMemory::from_code(&[1, 2, 3]);
+        memory.write(1 << 45, 5);
+        memory.write((1 << 45) + 1, 0);
+        assert_eq!(memory.read(1 << 45), 5);
+        assert_eq!(memory.read((1 << 45) + PAGE_SIZE), 0);
+        assert_eq!(memory.len(), (1 << 45) + 2);
+        assert_eq!(memory.pages.len(), 1);
+        let non_zero_cells: Vec<(usize, i64)> = memory.cells().into_iter().filter(|cell| cell.1 != 0).collect();
+        assert_eq!(non_zero_cells, [(0, 1), (1, 2), (2, 3), (1 << 45, 5)]);
+        memory.write(usize::MAX, 6);
+        assert_eq!(memory.read(usize::MAX), 6);
+        assert_eq!(memory.len(), usize::MAX);
+        assert_eq!(memory.cells().last(), Some(&(usize::MAX, 6)));
+    }
+
+    #[test]
+    fn sparse_memory_handles_huge_addresses() {
+        let mut memory = SparseMemory::default();
+        memory.write(1 << 40, 5);
+        assert_eq!(memory.read(1 << 40), 5);
+        assert_eq!(memory.cells(), [(1 << 40, 5)]);
+        memory.write(usize::MAX, 6);
+        assert_eq!(memory.len(), usize::MAX);
+    }
+}
@@ -0,0 +1,297 @@
+//! Rewrites code into equivalent but simpler code, keeping every instruction at its address and length:
+//! folds constant operands, resolves conditional jumps with a constant condition, and clears code that became unreachable.
+//!
+//! Only instructions reachable from address 0 are rewritten, and only if the program never writes their words
+//! (see `Assumptions`) and doesn't read them through a parameter in position mode.
+//! Accesses in relative mode can't be checked statically, run `verify` to compare both versions on some inputs.
+use crate::cfg::{self, ControlFlowGraph};
+use crate::disassembler::{Decoded, DisassembledLine, Parameter};
+use crate::{Opcode, ParameterMode, Program};
+use std::collections::{BTreeSet, VecDeque};
+use std::convert::TryFrom;
+
+/// What the optimizer may assume about the program at runtime.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Assumptions {
+    /// Addresses the program never writes to. Parameters in position mode reading them are constants.
+    pub never_written: BTreeSet<usize>,
+}
+
+impl Assumptions {
+    /// Assumes that the program never modifies its instructions, which holds for most compiled programs.
+    pub fn read_only_code(code: &[i64]) -> Assumptions {
+        let never_written = instructions(&cfg::extract(code))
+            .iter()
+            .flat_map(|line| line.address..line.address + line.words.len())
+            .collect();
+        return Assumptions { never_written };
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RewriteKind {
+    /// Parameters in position mode that read constants became immediate parameters.
+    ConstantParameter,
+    /// An `ADD`, `MUL`, `LT` or `EQ` with constant operands became `ADD #result, #0`.
+    Folded,
+    /// A conditional jump with a constant condition became `JNZ #1` or `JNZ #0`.
+    JumpResolved,
+    /// The instruction can't be reached anymore, its words were set to 0.
+    Unreachable,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Rewrite {
+    pub address: usize,
+    pub kind: RewriteKind,
+    pub before: Decoded,
+    /// None if the instruction was cleared.
+    pub after: Option<Decoded>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Optimized {
+    pub code: Vec<i64>,
+    /// Sorted by address.
+    pub rewrites: Vec<Rewrite>,
+}
+
+/// All instructions of the graph, without data words that blocks run into.
+fn instructions(graph: &ControlFlowGraph) -> Vec<&DisassembledLine> {
+    return graph
+        .blocks
+        .values()
+        .flat_map(|block| block.instructions.iter())
+        .filter(|line| matches!(line.decoded, Decoded::Instruction { .. }))
+        .collect();
+}
+
+fn immediate(value: i64) -> Parameter {
+    return Parameter { mode: ParameterMode::Immediate, value };
+}
+
+/// Simplifies a single instruction, given the values of constant memory cells. None if it can't be simplified.
+fn simplify(decoded: &Decoded, constant: impl Fn(i64) -> Option<i64>) -> Option<(RewriteKind, Decoded)> {
+    let (opcode, mut parameters) = match decoded {
+        Decoded::Instruction { opcode, parameters } => (*opcode, parameters.clone()),
+        Decoded::Data(_) => return None,
+    };
+    for (parameter_id, parameter) in parameters.iter_mut().enumerate() {
+        if parameter.mode == ParameterMode::Position && opcode.result_parameter() != Some(parameter_id + 1) {
+            if let Some(value) = constant(parameter.value) {
+                *parameter = immediate(value);
+            }
+        }
+    }
+    let value = |parameter: &Parameter| match parameter.mode {
+        ParameterMode::Immediate => Some(parameter.value),
+        _ => None,
+    };
+    let simplified = match opcode {
+        Opcode::Add | Opcode::Mul | Opcode::LessThan | Opcode::Equals => {
+            match (value(&parameters[0]), value(&parameters[1])) {
+                // overflows are left to fail at runtime
+                (Some(first), Some(second)) => match opcode {
+                    Opcode::Add => first.checked_add(second),
+                    Opcode::Mul => first.checked_mul(second),
+                    Opcode::LessThan => Some((first < second) as i64),
+                    _ => Some((first == second) as i64),
+                }
+                .map(|result| (RewriteKind::Folded, Opcode::Add, vec![immediate(result), immediate(0), parameters[2]])),
+                _ => None,
+            }
+        }
+        Opcode::JumpIfTrue | Opcode::JumpIfFalse => value(&parameters[0]).map(|condition| {
+            let taken = (condition != 0) == (opcode == Opcode::JumpIfTrue);
+            (RewriteKind::JumpResolved, Opcode::JumpIfTrue, vec![immediate(taken as i64), parameters[1]])
+        }),
+        _ => None,
+    };
+    let (kind, after) = match simplified {
+        Some((kind, opcode, parameters)) => (kind, Decoded::Instruction { opcode, parameters }),
+        None => (RewriteKind::ConstantParameter, Decoded::Instruction { opcode, parameters }),
+    };
+    return if after == *decoded { None } else { Some((kind, after)) };
+}
+
+/// Optimizes the code reachable from address 0.
+pub fn optimize(code: &[i64], assumptions: &Assumptions) -> Optimized {
+    let graph = cfg::extract(code);
+    let lines = instructions(&graph);
+    let mut read_as_data = BTreeSet::new();
+    let mut written = BTreeSet::new();
+    for line in lines.iter() {
+        if let Decoded::Instruction { opcode, parameters } = &line.decoded {
+            for (parameter_id, parameter) in parameters.iter().enumerate() {
+                match (parameter.mode, usize::try_from(parameter.value)) {
+                    (ParameterMode::Position, Ok(address)) if opcode.result_parameter() == Some(parameter_id + 1) => {
+                        written.insert(address);
+                    }
+                    (ParameterMode::Position, Ok(address)) => {
+                        read_as_data.insert(address);
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+    let is_constant = |address: usize| assumptions.never_written.contains(&address) && !written.contains(&address);
+    let is_rewritable = |line: &DisassembledLine| {
+        return (line.address..line.address + line.words.len())
+            .all(|address| is_constant(address) && !read_as_data.contains(&address));
+    };
+    let constant = |address: i64| match usize::try_from(address) {
+        Ok(address) if is_constant(address) => Some(*code.get(address).unwrap_or(&0)),
+        _ => None,
+    };
+
+    let mut optimized = code.to_vec();
+    let mut rewrites = Vec::new();
+    for line in lines.iter().filter(|line| is_rewritable(line)) {
+        if let Some((kind, after)) = simplify(&line.decoded, constant) {
+            optimized[line.address..line.address + line.words.len()].copy_from_slice(&after.encode());
+            rewrites.push(Rewrite { address: line.address, kind, before: line.decoded.clone(), after: Some(after) });
+        }
+    }
+    // with indirect jumps, any instruction may still be reachable
+    let optimized_graph = cfg::extract(&optimized);
+    if optimized_graph.indirect_jumps().is_empty() {
+        let reachable: BTreeSet<usize> = instructions(&optimized_graph).iter().map(|line| line.address).collect();
+        for line in lines.iter().filter(|line| is_rewritable(line) && !reachable.contains(&line.address)) {
+            for word in optimized[line.address..line.address + line.words.len()].iter_mut() {
+                *word = 0;
+            }
+            rewrites.retain(|rewrite| rewrite.address != line.address);
+            rewrites.push(Rewrite { address: line.address, kind: RewriteKind::Unreachable, before: line.decoded.clone(), after: None });
+        }
+    }
+    rewrites.sort_by_key(|rewrite| rewrite.address);
+    return Optimized { code: optimized, rewrites };
+}
+
+/// Runs the original and the optimized code side by side on the same inputs, for at most `max_steps` instructions.
+/// Both have to execute the same instructions with the same results, and end up with the same memory apart from rewritten words.
+/// Fails with a description of the first difference.
+pub fn verify(original: &[i64], optimized: &Optimized, inputs: &[i64], max_steps: usize) -> Result<(), String> {
+    let mut programs = [Program::init(original), Program::init(&optimized.code)];
+    let mut inputs: VecDeque<i64> = inputs.iter().cloned().collect();
+    for step in 0..max_steps {
+        let states: Vec<(usize, usize, bool)> = programs
+            .iter()
+            .map(|program| (program.instruction_pointer(), program.relative_base(), program.will_terminate()))
+            .collect();
+        if states[0] != states[1] {
+            return Err(format!(
+                "step {}: original at #{} (rb {}), optimized at #{} (rb {})",
+                step, states[0].0, states[0].1, states[1].0, states[1].1
+            ));
+        }
+        if states[0].2 {
+            break;
+        }
+        let input = match programs[0].next_opcode() {
+            Ok(opcode) if opcode.reads_input() => inputs.pop_front(),
+            _ => None,
+        };
+        let original_result = programs[0].step(input);
+        let optimized_result = programs[1].step(input);
+        match (&original_result, &optimized_result) {
+            (Ok(original_output), Ok(optimized_output)) if original_output == optimized_output => (),
+            (Err(original_error), Err(optimized_error)) if original_error.reason == optimized_error.reason => break,
+            _ => {
+                return Err(format!(
+                    "step {} at #{}: original returned {:?}, optimized {:?}",
+                    step, states[0].0, original_result, optimized_result
+                ));
+            }
+        }
+    }
+    let rewritten: BTreeSet<usize> = optimized
+        .rewrites
+        .iter()
+        .flat_map(|rewrite| rewrite.address..rewrite.address + rewrite.before.encode().len())
+        .collect();
+    let memories = [programs[0].memory_as_vec(), programs[1].memory_as_vec()];
+    if memories[0].len() != memories[1].len() {
+        return Err(format!("memory lengths differ: {} and {}", memories[0].len(), memories[1].len()));
+    }
+    for address in (0..memories[0].len()).filter(|address| !rewritten.contains(address)) {
+        if memories[0][address] != memories[1][address] {
+            return Err(format!("memory differs at {}: {} and {}", address, memories[0][address], memories[1][address]));
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn fold_constants_and_resolve_jumps() {
+        let source = "
+                    IN [x]
+                    ADD #2, #3, [y]
+                    MUL [k], #4, [z]
+                    JZ [zero], #skip
+                    OUT #1
+            skip:   OUT [x]
+                    HLT
+            x:      .data 0
+            y:      .data 0
+            z:      .data 0
+            k:      .data 6
+            zero:   .data 0
+        ";
+        let code = assemble(source).unwrap();
+        let mut assumptions = Assumptions::read_only_code(&code);
+        assumptions.never_written.extend([21, 22].iter());
+        let optimized = optimize(&code, &assumptions);
+        assert_eq!(optimized.code[2..15], [1101, 5, 0, 19, 1101, 24, 0, 20, 1105, 1, 15, 0, 0]);
+        assert_eq!(optimized.code[15..], code[15..]);
+        let rewrites: Vec<(usize, RewriteKind)> = optimized.rewrites.iter().map(|rewrite| (rewrite.address, rewrite.kind)).collect();
+        assert_eq!(
+            rewrites,
+            [(2, RewriteKind::Folded), (6, RewriteKind::Folded), (10, RewriteKind::JumpResolved), (13, RewriteKind::Unreachable)]
+        );
+        assert_eq!(optimized.rewrites[2].after.as_ref().unwrap().to_string(), "JNZ #1, #15");
+        assert_eq!(verify(&code, &optimized, &[7], 100), Ok(()));
+        assert_eq!(Program::init(&optimized.code).run(vec![7]).unwrap(), [7]);
+    }
+
+    #[test]
+    fn instructions_read_as_data_are_kept() {
+        // ADD #2, #3, [7]; OUT [1]; HLT
+        let code = [1101, 2, 3, 7, 4, 1, 99, 0];
+        let optimized = optimize(&code, &Assumptions::read_only_code(&code));
+        assert_eq!(optimized.code, [1101, 2, 3, 7, 104, 2, 99, 0]);
+        assert_eq!(optimized.rewrites.len(), 1);
+        assert_eq!(optimized.rewrites[0].kind, RewriteKind::ConstantParameter);
+        assert_eq!(verify(&code, &optimized, &[], 100), Ok(()));
+    }
+
+    #[test]
+    fn words_with_extra_mode_digits_are_kept() {
+        // OUT [5] with a mode digit for a missing second parameter, then HLT with one for a missing first parameter
+        let code = [10004, 5, 1199, 0, 0, 7];
+        let optimized = optimize(&code, &Assumptions::read_only_code(&code));
+        assert_eq!(optimized.code, code);
+        assert_eq!(optimized.rewrites, []);
+        assert_eq!(verify(&code, &optimized, &[], 100), Ok(()));
+    }
+
+    #[test]
+    fn verify_detects_wrong_assumptions() {
+        // the write through the relative base isn't known statically
+        let code = assemble("ARB #9\n ADD #1, #0, rb+0\n OUT [9]\n HLT\n .data 5").unwrap();
+        let mut assumptions = Assumptions::read_only_code(&code);
+        assumptions.never_written.insert(9);
+        let optimized = optimize(&code, &assumptions);
+        assert_eq!(optimized.code[6..8], [104, 5]);
+        assert_eq!(
+            verify(&code, &optimized, &[], 100),
+            Err("step 2 at #6: original returned Ok(Some(1)), optimized Ok(Some(5))".to_string())
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! What happens when `ADD` or `MUL` overflow, see `Program::set_overflow_policy`.
+use crate::{Memory, Opcode, Program};
+use std::convert::TryFrom;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OverflowPolicy {
+    /// The instruction fails with `ErrorReason::ArithmeticOverflow`.
+    Checked,
+    /// The result wraps around, like two's complement hardware would.
+    Wrapping,
+    /// The result is clamped to the smallest or largest 64 bit value.
+    Saturating,
+    /// Results are stored with 128 bits. Only arithmetic, comparisons and jump conditions accept such wide values,
+    /// using one as output, address, jump target or relative base offset fails with `ErrorReason::WideValue`.
+    Wide,
+}
+
+impl Default for OverflowPolicy {
+    fn default() -> OverflowPolicy {
+        return OverflowPolicy::Checked;
+    }
+}
+
+impl OverflowPolicy {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            OverflowPolicy::Checked => "checked",
+            OverflowPolicy::Wrapping => "wrapping",
+            OverflowPolicy::Saturating => "saturating",
+            OverflowPolicy::Wide => "wide",
+        };
+    }
+
+    pub fn from_name(name: &str) -> Option<OverflowPolicy> {
+        return match name {
+            "checked" => Some(OverflowPolicy::Checked),
+            "wrapping" => Some(OverflowPolicy::Wrapping),
+            "saturating" => Some(OverflowPolicy::Saturating),
+            "wide" => Some(OverflowPolicy::Wide),
+            _ => None,
+        };
+    }
+
+    /// The result of an `ADD` or `MUL`, None if the operation overflows under this policy.
+    /// Operands that don't fit into 64 bits are only accepted by `Wide`.
+    pub fn evaluate(&self, opcode: Opcode, first_operand: i128, second_operand: i128) -> Option<i128> {
+        if *self == OverflowPolicy::Wide {
+            return match opcode {
+                Opcode::Add => first_operand.checked_add(second_operand),
+                _ => first_operand.checked_mul(second_operand),
+            };
+        }
+        let first_operand = i64::try_from(first_operand).ok()?;
+        let second_operand = i64::try_from(second_operand).ok()?;
+        let result = match (self, opcode) {
+            (OverflowPolicy::Wrapping, Opcode::Add) => first_operand.wrapping_add(second_operand),
+            (OverflowPolicy::Wrapping, _) => first_operand.wrapping_mul(second_operand),
+            (OverflowPolicy::Saturating, Opcode::Add) => first_operand.saturating_add(second_operand),
+            (OverflowPolicy::Saturating, _) => first_operand.saturating_mul(second_operand),
+            (_, Opcode::Add) => first_operand.checked_add(second_operand)?,
+            (_, _) => first_operand.checked_mul(second_operand)?,
+        };
+        return Some(result as i128);
+    }
+}
+
+impl fmt::Display for OverflowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl<M: Memory> Program<M> {
+    /// Switches the overflow policy, the default is `OverflowPolicy::Checked`.
+    /// Wide values written so far are kept, but other policies fail when using them.
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.overflow_policy = policy;
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        return self.overflow_policy;
+    }
+
+    /// Like `read_memory`, but also returns values that only fit into 128 bits.
+    /// `read_memory` returns their lower 64 bits.
+    pub fn read_wide_memory(&self, at: usize) -> i128 {
+        return match self.wide_cells.get(&at) {
+            Some(value) => *value,
+            None => self.read_memory(at) as i128,
+        };
+    }
+
+    /// Like `set_memory`, but values that don't fit into 64 bits are stored in full.
+    pub fn set_wide_memory(&mut self, address: usize, value: i128) {
+        match i64::try_from(value) {
+            Ok(value) => self.set_memory(address, value),
+            Err(_) => {
+                self.set_memory(address, value as i64);
+                self.wide_cells.insert(address, value);
+            }
+        }
+    }
+
+    /// Addresses of all cells holding values that don't fit into 64 bits, in ascending order.
+    pub fn wide_addresses(&self) -> Vec<usize> {
+        let mut addresses: Vec<usize> = self.wide_cells.keys().cloned().collect();
+        addresses.sort_unstable();
+        return addresses;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ErrorReason;
+
+    // [7] = [7] * [7], then outputs [7]
+    const SQUARE: [i64; 8] = [2, 7, 7, 7, 4, 7, 99, 0];
+
+    fn square(value: i64, policy: OverflowPolicy) -> Program {
+        let mut program = Program::init(&SQUARE);
+        program.set_memory(7, value);
+        program.set_overflow_policy(policy);
+        return program;
+    }
+
+    #[test]
+    fn policies() {
+        let large = 1 << 40;
+        assert_eq!(OverflowPolicy::Checked.evaluate(Opcode::Add, i64::MAX as i128, 1), None);
+        assert_eq!(OverflowPolicy::Checked.evaluate(Opcode::Mul, -3, 4), Some(-12));
+        assert_eq!(OverflowPolicy::Wrapping.evaluate(Opcode::Add, i64::MAX as i128, 1), Some(i64::MIN as i128));
+        assert_eq!(OverflowPolicy::Saturating.evaluate(Opcode::Mul, -large, large), Some(i64::MIN as i128));
+        assert_eq!(OverflowPolicy::Wide.evaluate(Opcode::Mul, large, large), Some(1 << 80));
+        assert_eq!(OverflowPolicy::Wrapping.evaluate(Opcode::Add, 1 << 80, 0), None);
+        for policy in [OverflowPolicy::Checked, OverflowPolicy::Wrapping, OverflowPolicy::Saturating, OverflowPolicy::Wide].iter() {
+            assert_eq!(OverflowPolicy::from_name(policy.name()), Some(*policy));
+        }
+    }
+
+    #[test]
+    fn checked_overflow_fails() {
+        let mut program = square(1 << 32, OverflowPolicy::default());
+        let error = program.run(Vec::new()).err().unwrap();
+        assert_eq!(error.instruction_pointer, 0);
+        assert_eq!(
+            error.reason,
+            ErrorReason::ArithmeticOverflow { opcode: Opcode::Mul, first_operand: 1 << 32, second_operand: 1 << 32 }
+        );
+        assert_eq!(program.read_memory(7), 1 << 32);
+    }
+
+    #[test]
+    fn wrapping_and_saturating() {
+        assert_eq!(square(1 << 32, OverflowPolicy::Wrapping).run(Vec::new()).unwrap(), [0]);
+        assert_eq!(square((1 << 32) + 1, OverflowPolicy::Wrapping).run(Vec::new()).unwrap(), [(1 << 33) + 1]);
+        assert_eq!(square(-(1 << 32), OverflowPolicy::Saturating).run(Vec::new()).unwrap(), [i64::MAX]);
+    }
+
+    #[test]
+    fn wide_values() {
+        // [11] = [10] * [10], [12] = [10] < [11]
+        let code = [2, 10, 10, 11, 7, 10, 11, 12, 99, 0, 1 << 40, 0, 0];
+        let mut program = Program::init(&code);
+        program.set_overflow_policy(OverflowPolicy::Wide);
+        program.run(Vec::new()).unwrap();
+        assert_eq!(program.read_wide_memory(11), 1 << 80);
+        assert_eq!(program.read_memory(11), 0);
+        assert_eq!(program.read_memory(12), 1);
+        assert_eq!(program.wide_addresses(), [11]);
+        let error = square(1 << 40, OverflowPolicy::Wide).run(Vec::new()).err().unwrap();
+        assert_eq!(error.instruction_pointer, 4);
+        assert_eq!(error.reason, ErrorReason::WideValue(1 << 80));
+        let mut program = square(1 << 40, OverflowPolicy::Wide);
+        program.step(None).unwrap();
+        program.set_memory(7, 5);
+        assert_eq!(program.wide_addresses(), []);
+        assert_eq!(program.run(Vec::new()).unwrap(), [5]);
+    }
+}
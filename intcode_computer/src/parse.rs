@@ -0,0 +1,192 @@
+//! Parses program text, i.e. comma separated integers like `1,9,10,3,2,3,11,0,99,30,40,50`.
+//!
+//! Whitespace around numbers is ignored, line breaks separate numbers just like commas do,
+//! and a trailing comma at the end of a line or of the input is allowed.
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+/// Where a token starts. Lines and columns start at 1, columns and offsets count bytes.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+    pub offset: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    Io(io::Error),
+    InvalidNumber { position: Position, token: String },
+    /// Two commas without a number in between, or a comma at the start of a line.
+    MissingNumber { position: Position },
+    /// Two numbers that are only separated by spaces.
+    MissingSeparator { position: Position },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Io(error) => write!(f, "{}", error),
+            ParseError::InvalidNumber { position, token } => write!(f, "{}: invalid number '{}'", position, token),
+            ParseError::MissingNumber { position } => write!(f, "{}: expected a number before ','", position),
+            ParseError::MissingSeparator { position } => write!(f, "{}: expected ',' between numbers", position),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(error: io::Error) -> ParseError {
+        return ParseError::Io(error);
+    }
+}
+
+#[derive(PartialEq)]
+enum State {
+    /// At the start of the input or of a line, or after a line break.
+    LineStart,
+    AfterComma,
+    InNumber,
+    AfterNumber,
+}
+
+struct Parser {
+    code: Vec<i64>,
+    state: State,
+    token: Vec<u8>,
+    token_start: Position,
+    position: Position,
+}
+
+impl Parser {
+    fn finish_number(&mut self) -> Result<(), ParseError> {
+        if self.state != State::InNumber {
+            return Ok(());
+        }
+        let token = String::from_utf8_lossy(&self.token).to_string();
+        let value = token.parse().map_err(|_| ParseError::InvalidNumber {
+            position: self.token_start,
+            token: token.clone(),
+        })?;
+        self.code.push(value);
+        self.token.clear();
+        self.state = State::AfterNumber;
+        return Ok(());
+    }
+
+    fn feed(&mut self, byte: u8) -> Result<(), ParseError> {
+        match byte {
+            b'\n' => {
+                self.finish_number()?;
+                self.state = State::LineStart;
+            }
+            b',' => {
+                self.finish_number()?;
+                if self.state != State::AfterNumber {
+                    return Err(ParseError::MissingNumber { position: self.position });
+                }
+                self.state = State::AfterComma;
+            }
+            byte if byte.is_ascii_whitespace() => self.finish_number()?,
+            byte => {
+                if self.state == State::AfterNumber {
+                    return Err(ParseError::MissingSeparator { position: self.position });
+                }
+                if self.state != State::InNumber {
+                    self.token_start = self.position;
+                    self.state = State::InNumber;
+                }
+                self.token.push(byte);
+            }
+        }
+        if byte == b'\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+        self.position.offset += 1;
+        return Ok(());
+    }
+}
+
+/// Parses program text, read piece by piece, so the whole text never has to be in memory at once.
+pub fn parse_program_reader(mut reader: impl BufRead) -> Result<Vec<i64>, ParseError> {
+    let start = Position { line: 1, column: 1, offset: 0 };
+    let mut parser = Parser {
+        code: Vec::new(),
+        state: State::LineStart,
+        token: Vec::new(),
+        token_start: start,
+        position: start,
+    };
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            break;
+        }
+        for byte in buffer.iter() {
+            parser.feed(*byte)?;
+        }
+        let length = buffer.len();
+        reader.consume(length);
+    }
+    parser.finish_number()?;
+    return Ok(parser.code);
+}
+
+pub fn parse_program_str(input_string: &str) -> Result<Vec<i64>, ParseError> {
+    return parse_program_reader(input_string.as_bytes());
+}
+
+pub fn parse_program_file(path: impl AsRef<Path>) -> Result<Vec<i64>, ParseError> {
+    return parse_program_reader(BufReader::new(File::open(path)?));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn error(input: &str) -> String {
+        return parse_program_str(input).err().unwrap().to_string();
+    }
+
+    #[test]
+    fn tolerates_whitespace_and_line_endings() {
+        assert_eq!(parse_program_str("1,2,3\n").unwrap(), [1, 2, 3]);
+        assert_eq!(parse_program_str("1,2,3").unwrap(), [1, 2, 3]);
+        assert_eq!(parse_program_str("1,2,3\r\n").unwrap(), [1, 2, 3]);
+        assert_eq!(parse_program_str(" 1 , -2,\t3 ,\n").unwrap(), [1, -2, 3]);
+        assert_eq!(parse_program_str("1,2,\n3,4\n\n5\n").unwrap(), [1, 2, 3, 4, 5]);
+        assert_eq!(parse_program_str("").unwrap(), []);
+    }
+
+    #[test]
+    fn reports_positions() {
+        assert_eq!(error("1,2,x3,4\n"), "line 1, column 5: invalid number 'x3'");
+        assert_eq!(error("1,2,\n3,,4"), "line 2, column 3: expected a number before ','");
+        assert_eq!(error(",1"), "line 1, column 1: expected a number before ','");
+        assert_eq!(error("1,2 3"), "line 1, column 5: expected ',' between numbers");
+        assert_eq!(error("1,99999999999999999999"), "line 1, column 3: invalid number '99999999999999999999'");
+        match parse_program_str("1,\r\n2,-").err().unwrap() {
+            ParseError::InvalidNumber { position, .. } => assert_eq!(position, Position { line: 2, column: 3, offset: 6 }),
+            error => panic!("unexpected error {}", error),
+        }
+    }
+
+    #[test]
+    fn reads_in_small_pieces() {
+        let reader = BufReader::with_capacity(3, "109,-1,204,1\n99".as_bytes());
+        assert_eq!(parse_program_reader(reader).unwrap(), [109, -1, 204, 1, 99]);
+    }
+}
@@ -0,0 +1,133 @@
+//! Counts executed instructions, see `Program::enable_profiling`.
+use crate::disassembler::disassemble_at;
+use crate::{Memory, Opcode, Program};
+use std::collections::{HashMap, HashSet};
+
+/// Execution counts since profiling was enabled.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    pub total: u64,
+    /// Number of executions by the address of the executed instruction.
+    pub executions: HashMap<usize, u64>,
+    pub opcodes: HashMap<Opcode, u64>,
+    /// Addresses of all words executed as part of an instruction, including parameters.
+    pub covered: HashSet<usize>,
+    /// Length of the program's memory when profiling was enabled, coverage is relative to it.
+    pub code_length: usize,
+}
+
+impl Profile {
+    pub(crate) fn new(code_length: usize) -> Profile {
+        return Profile {
+            code_length,
+            ..Profile::default()
+        };
+    }
+
+    pub(crate) fn record(&mut self, instruction_pointer: usize, opcode: Opcode) {
+        self.total += 1;
+        *self.executions.entry(instruction_pointer).or_insert(0) += 1;
+        *self.opcodes.entry(opcode).or_insert(0) += 1;
+        self.covered.extend(instruction_pointer..=instruction_pointer + opcode.parameter_count());
+    }
+
+    /// Share of the code's words that were executed, between 0 and 1.
+    pub fn coverage(&self) -> f64 {
+        if self.code_length == 0 {
+            return 0.0;
+        }
+        let covered_code = self.covered.iter().filter(|address| **address < self.code_length).count();
+        return covered_code as f64 / self.code_length as f64;
+    }
+
+    /// Addresses of the most often executed instructions together with their counts, most executed first.
+    pub fn hot_spots(&self, count: usize) -> Vec<(usize, u64)> {
+        let mut hot_spots: Vec<(usize, u64)> = self.executions.iter().map(|(address, count)| (*address, *count)).collect();
+        hot_spots.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hot_spots.truncate(count);
+        return hot_spots;
+    }
+
+    fn share(&self, count: u64) -> f64 {
+        return 100.0 * count as f64 / self.total.max(1) as f64;
+    }
+
+    /// Renders the totals, the given number of hot spots disassembled from the program's memory, and the opcode mix.
+    pub fn report<M: Memory>(&self, program: &Program<M>, hot_spot_count: usize) -> String {
+        let mut report = format!("instructions executed: {}\n", self.total);
+        let covered_code = self.covered.iter().filter(|address| **address < self.code_length).count();
+        report += &format!(
+            "coverage: {} of {} words ({:.1}%)\n",
+            covered_code,
+            self.code_length,
+            100.0 * self.coverage()
+        );
+        report += "\nhot spots:\n";
+        for (address, count) in self.hot_spots(hot_spot_count) {
+            report += &format!("{:>10} {:>5.1}% {}\n", count, self.share(count), disassemble_at(program, address));
+        }
+        report += "\nopcodes:\n";
+        let mut opcodes: Vec<(&Opcode, &u64)> = self.opcodes.iter().collect();
+        opcodes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.number().cmp(&b.0.number())));
+        for (opcode, count) in opcodes {
+            report += &format!("{:>10} {:>5.1}% {}\n", count, self.share(*count), opcode.mnemonic());
+        }
+        return report;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+
+    // counts [x] down from 3, the code after HLT is never executed
+    const COUNTDOWN: &str = "
+        loop:   ADD [x], #-1, [x]
+                JNZ [x], #loop
+                HLT
+                OUT #1
+        x:      .data 3
+    ";
+
+    #[test]
+    fn profiling_is_opt_in() {
+        let mut program = Program::init(&assemble(COUNTDOWN).unwrap());
+        program.run(Vec::new()).unwrap();
+        assert!(program.profile().is_none());
+    }
+
+    #[test]
+    fn executions_are_counted() {
+        let mut program = Program::init(&assemble(COUNTDOWN).unwrap());
+        program.enable_profiling();
+        program.run(Vec::new()).unwrap();
+        let profile = program.profile().unwrap();
+        assert_eq!(profile.total, 6);
+        assert_eq!(profile.hot_spots(5), [(0, 3), (4, 3)]);
+        assert_eq!(profile.opcodes[&Opcode::Add], 3);
+        assert_eq!(profile.opcodes.get(&Opcode::Terminate), None);
+        assert_eq!(profile.covered.len(), 7);
+        assert_eq!(profile.coverage(), 7.0 / 11.0);
+    }
+
+    #[test]
+    fn report() {
+        let mut program = Program::init(&assemble(COUNTDOWN).unwrap());
+        program.enable_profiling();
+        program.run(Vec::new()).unwrap();
+        assert_eq!(
+            program.profile().unwrap().report(&program, 1),
+            "instructions executed: 6
+coverage: 7 of 11 words (63.6%)
+
+hot spots:
+         3  50.0%      0: 1001 10 -1 10                    ADD [10], #-1, [10]
+
+opcodes:
+         3  50.0% ADD
+         3  50.0% JNZ
+"
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Detects programs that modify their own code, see `Program::enable_self_modification_detection`.
+//!
+//! Every word of an executed instruction, including its parameters, counts as code.
+//! Only writes done by the program's instructions are considered, not those done via `Program::set_memory`.
+use crate::accesses::InstructionAccesses;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SelfModification {
+    /// The instruction at `writer` wrote to `address`, which was executed as part of an instruction before.
+    WriteToExecuted { address: usize, writer: usize },
+    /// The instruction at `instruction_pointer` was executed, but its word at `address` was written by the instruction at `writer`.
+    /// Only reported once per write.
+    ExecutedAfterWrite { address: usize, writer: usize, instruction_pointer: usize },
+}
+
+/// Which addresses were executed and written, and all self modifications found so far.
+#[derive(Debug, Clone, Default)]
+pub struct SelfModificationDetector {
+    executed: HashSet<usize>,
+    /// Written addresses that weren't executed since, by the address of the writing instruction.
+    written: HashMap<usize, usize>,
+    pub modifications: Vec<SelfModification>,
+}
+
+impl SelfModificationDetector {
+    /// Records an instruction that was executed successfully.
+    pub(crate) fn record(&mut self, instruction: &InstructionAccesses) {
+        let instruction_pointer = instruction.instruction_pointer;
+        for address in instruction_pointer..instruction_pointer + instruction.length {
+            if let Some(writer) = self.written.remove(&address) {
+                self.modifications.push(SelfModification::ExecutedAfterWrite { address, writer, instruction_pointer });
+            }
+            self.executed.insert(address);
+        }
+        if let Some(address) = instruction.write {
+            if self.executed.contains(&address) {
+                self.modifications.push(SelfModification::WriteToExecuted { address, writer: instruction_pointer });
+            }
+            self.written.insert(address, instruction_pointer);
+        }
+    }
+
+    pub fn was_executed(&self, address: usize) -> bool {
+        return self.executed.contains(&address);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::Program;
+
+    #[test]
+    fn detection_is_opt_in() {
+        let mut program = Program::init(&[1101, 2, 3, 0, 99]);
+        program.run(Vec::new()).unwrap();
+        assert!(program.self_modifications().is_none());
+    }
+
+    #[test]
+    fn write_to_executed_instruction() {
+        // overwrites its own opcode
+        let mut program = Program::init(&[1101, 2, 3, 0, 99]);
+        program.enable_self_modification_detection();
+        program.run(Vec::new()).unwrap();
+        let detector = program.self_modifications().unwrap();
+        assert_eq!(detector.modifications, [SelfModification::WriteToExecuted { address: 0, writer: 0 }]);
+        assert!(detector.was_executed(3));
+        assert!(!detector.was_executed(5));
+    }
+
+    #[test]
+    fn execution_of_written_instruction() {
+        // patches the value printed by the OUT instruction before running it
+        let source = "
+                    ADD #7, #0, [9]
+            again:  JZ [done], #patch
+                    HLT
+            patch:  OUT #0
+                    ADD #1, #0, [done]
+                    JNZ #1, #again
+            done:   .data 0
+        ";
+        let mut program = Program::init(&assemble(source).unwrap());
+        program.enable_self_modification_detection();
+        assert_eq!(program.run(Vec::new()).unwrap(), [7]);
+        assert_eq!(
+            program.self_modifications().unwrap().modifications,
+            [SelfModification::ExecutedAfterWrite { address: 9, writer: 0, instruction_pointer: 8 }]
+        );
+    }
+
+    #[test]
+    fn data_writes_are_not_reported() {
+        let mut program = Program::init(&[3, 5, 4, 5, 99]);
+        program.enable_self_modification_detection();
+        program.run(vec![12]).unwrap();
+        assert!(program.self_modifications().unwrap().modifications.is_empty());
+    }
+}
@@ -0,0 +1,301 @@
+//! Saves and restores the full state of a program in a versioned, line-based text format:
+//!
+//! ```text
+//! intcode-snapshot 3
+//! instruction_pointer 12
+//! relative_base 2000
+//! overflow_policy checked
+//! input 1,-1
+//! memory 0 1,380,379,385
+//! memory 2000 7,0,0,12
+//! ```
+//!
+//! Each `memory` line holds a start address and the values of a contiguous run of memory cells, cells that aren't listed hold 0.
+//! The `input` line holds inputs provided to the program which haven't been consumed yet, it's omitted if there are none.
+//! Memory values may exceed 64 bits, see `OverflowPolicy::Wide`.
+//! Older snapshots can still be loaded: version 1 can't hold pending inputs, versions 1 and 2 lack the overflow policy.
+use crate::{Memory, OverflowPolicy, PagedMemory, Program};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+const HEADER: &str = "intcode-snapshot";
+const VERSION: u32 = 3;
+/// The first versions holding pending inputs and the overflow policy.
+const INPUT_VERSION: u32 = 2;
+const OVERFLOW_POLICY_VERSION: u32 = 3;
+/// Longest run of zero cells that is written out instead of starting a new `memory` line.
+const MAX_ZERO_GAP: usize = 16;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    UnsupportedVersion(String),
+    Invalid { line: usize, message: String },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(error) => write!(f, "{}", error),
+            SnapshotError::UnsupportedVersion(version) => write!(f, "unsupported snapshot version '{}'", version),
+            SnapshotError::Invalid { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> SnapshotError {
+        return SnapshotError::Io(error);
+    }
+}
+
+impl<M: Memory> Program<M> {
+    /// Writes the program's state, so it can be restored with `Program::load_snapshot` later on.
+    pub fn save_snapshot(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "{} {}", HEADER, VERSION)?;
+        writeln!(writer, "instruction_pointer {}", self.instruction_pointer)?;
+        writeln!(writer, "relative_base {}", self.relative_base)?;
+        writeln!(writer, "overflow_policy {}", self.overflow_policy)?;
+        if !self.pending_inputs.is_empty() {
+            let inputs: Vec<String> = self.pending_inputs.iter().map(|input| input.to_string()).collect();
+            writeln!(writer, "input {}", inputs.join(","))?;
+        }
+        // zero cells are skipped unless they're in short gaps between other values,
+        // the last cell is always written so the restored memory has the same length
+        let mut cells: Vec<(usize, i64)> = self.memory.cells().into_iter().filter(|cell| cell.1 != 0).collect();
+        if !self.wide_cells.is_empty() {
+            // a wide value's lower 64 bits may be 0
+            cells.extend(self.wide_cells.keys().map(|address| (*address, 0)));
+            cells.sort_unstable();
+            cells.dedup_by_key(|cell| cell.0);
+        }
+        // a saturated length may include the last address, which is then written as well
+        let last_address = match self.memory.len() {
+            usize::MAX => Some(usize::MAX),
+            len => len.checked_sub(1),
+        };
+        match (cells.last(), last_address) {
+            (Some(cell), Some(last_address)) if cell.0 >= last_address => (),
+            (_, Some(last_address)) => cells.push((last_address, 0)),
+            (_, None) => (),
+        }
+        let mut run_start = 0;
+        while run_start < cells.len() {
+            let mut run_end = run_start + 1;
+            while run_end < cells.len() && cells[run_end].0 - cells[run_end - 1].0 <= MAX_ZERO_GAP + 1 {
+                run_end += 1;
+            }
+            let start_address = cells[run_start].0;
+            let end_address = cells[run_end - 1].0;
+            let values: Vec<String> = (start_address..=end_address).map(|address| self.read_wide_memory(address).to_string()).collect();
+            writeln!(writer, "memory {} {}", start_address, values.join(","))?;
+            run_start = run_end;
+        }
+        return Ok(());
+    }
+
+    pub fn save_snapshot_file(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.save_snapshot(&mut writer)?;
+        return writer.flush();
+    }
+
+    /// Restores a program from a snapshot written by `Program::save_snapshot`, into the given (usually empty) memory.
+    pub fn load_snapshot_into(reader: impl BufRead, memory: M) -> Result<Program<M>, SnapshotError> {
+        let mut program = Program::with_memory(memory);
+        // 0 until the header was read
+        let mut version = 0;
+        for (line_id, line) in reader.lines().enumerate() {
+            let line = line?;
+            let invalid = |message: &str| SnapshotError::Invalid {
+                line: line_id + 1,
+                message: message.to_string(),
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if line_id == 0 {
+                match words.as_slice() {
+                    [HEADER, number] => match number.parse() {
+                        Ok(number) if (1..=VERSION).contains(&number) => {
+                            version = number;
+                            continue;
+                        }
+                        _ => return Err(SnapshotError::UnsupportedVersion(number.to_string())),
+                    },
+                    _ => return Err(invalid("not an intcode snapshot")),
+                }
+            }
+            match words.as_slice() {
+                [] => (),
+                ["instruction_pointer", value] => {
+                    program.instruction_pointer = value.parse().map_err(|_| invalid("invalid instruction pointer"))?;
+                }
+                ["relative_base", value] => {
+                    program.relative_base = value.parse().map_err(|_| invalid("invalid relative base"))?;
+                }
+                ["overflow_policy", name] if version >= OVERFLOW_POLICY_VERSION => {
+                    program.overflow_policy = OverflowPolicy::from_name(name).ok_or_else(|| invalid("unknown overflow policy"))?;
+                }
+                ["input", inputs] if version >= INPUT_VERSION => {
+                    for input in inputs.split(',') {
+                        program.provide_input(input.parse().map_err(|_| invalid("invalid input"))?);
+                    }
+                }
+                ["memory", start, values] => {
+                    let start: usize = start.parse().map_err(|_| invalid("invalid memory address"))?;
+                    for (offset, value) in values.split(',').enumerate() {
+                        let value = value.parse().map_err(|_| invalid("invalid memory value"))?;
+                        let address = start.checked_add(offset).ok_or_else(|| invalid("invalid memory address"))?;
+                        program.set_wide_memory(address, value);
+                    }
+                }
+                _ => return Err(invalid("unknown entry")),
+            }
+        }
+        if version == 0 {
+            return Err(SnapshotError::Invalid { line: 1, message: "not an intcode snapshot".to_string() });
+        }
+        return Ok(program);
+    }
+}
+
+impl Program {
+    /// Restores a program from a snapshot written by `Program::save_snapshot`.
+    pub fn load_snapshot(reader: impl BufRead) -> Result<Program, SnapshotError> {
+        return Program::load_snapshot_into(reader, PagedMemory::default());
+    }
+
+    pub fn load_snapshot_file(path: impl AsRef<Path>) -> Result<Program, SnapshotError> {
+        return Program::load_snapshot(BufReader::new(File::open(path)?));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{RunState, SparseMemory};
+
+    fn save<M: Memory>(program: &Program<M>) -> String {
+        let mut snapshot = Vec::new();
+        program.save_snapshot(&mut snapshot).unwrap();
+        return String::from_utf8(snapshot).unwrap();
+    }
+
+    #[test]
+    fn snapshot_format() {
+        let mut program = Program::init(&[109, 19, 3, 0, 99]);
+        program.step(None).unwrap();
+        program.set_memory(100, -7);
+        program.set_memory(101, 8);
+        assert_eq!(
+            save(&program),
+            "intcode-snapshot 3\ninstruction_pointer 2\nrelative_base 19\noverflow_policy checked\nmemory 0 109,19,3,0,99\nmemory 100 -7,8\n"
+        );
+        program.provide_input(5);
+        program.provide_input(-6);
+        assert!(save(&program).contains("\noverflow_policy checked\ninput 5,-6\nmemory 0 "));
+    }
+
+    #[test]
+    fn pending_inputs_are_restored() {
+        let mut program = Program::init(&[3, 0, 4, 0, 3, 0, 4, 0, 99]);
+        program.provide_input(5);
+        program.provide_input(-6);
+        assert_eq!(program.resume(), Ok(RunState::Output(5)));
+        let mut restored = Program::load_snapshot(save(&program).as_bytes()).unwrap();
+        assert_eq!(restored.resume(), Ok(RunState::Output(-6)));
+        assert_eq!(restored.resume(), Ok(RunState::Halted));
+    }
+
+    #[test]
+    fn version_1_snapshots_can_be_loaded() {
+        let snapshot = "intcode-snapshot 1\ninstruction_pointer 2\nrelative_base 19\nmemory 0 109,19,4,0,99\n";
+        let mut program = Program::load_snapshot(snapshot.as_bytes()).unwrap();
+        assert_eq!(program.run(Vec::new()).unwrap(), [109]);
+    }
+
+    #[test]
+    fn restored_program_continues() {
+        // counts down from the input, outputting every number
+        let code = [3, 13, 4, 13, 1001, 13, -1, 13, 1005, 13, 2, 99, 0, 0];
+        let mut program = Program::init(&code);
+        program.step(Some(5)).unwrap();
+        for _ in 0..6 {
+            program.step(None).unwrap();
+        }
+        let mut restored = Program::load_snapshot(save(&program).as_bytes()).unwrap();
+        assert_eq!(restored.instruction_pointer, program.instruction_pointer);
+        assert_eq!(restored.memory_as_vec(), program.memory_as_vec());
+        assert_eq!(restored.run(Vec::new()).unwrap(), program.run(Vec::new()).unwrap());
+    }
+
+    #[test]
+    fn snapshot_file_round_trip() {
+        let path = std::env::temp_dir().join(format!("intcode-snapshot-test-{}", std::process::id()));
+        let mut program = Program::init(&[109, -1, 99]);
+        program.relative_base = 4;
+        program.save_snapshot_file(&path).unwrap();
+        let restored = Program::load_snapshot_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restored.relative_base, 4);
+        assert_eq!(restored.memory_as_vec(), [109, -1, 99]);
+    }
+
+    #[test]
+    fn sparse_memory_round_trip() {
+        let mut program = Program::with_memory(SparseMemory::from_code(&[104, 1, 99]));
+        program.set_memory(1 << 40, 3);
+        program.set_memory(50, 0);
+        let mut snapshot = Vec::new();
+        program.save_snapshot(&mut snapshot).unwrap();
+        assert!(String::from_utf8(snapshot.clone()).unwrap().ends_with("\nmemory 0 104,1,99\nmemory 1099511627776 3\n"));
+        let restored = Program::load_snapshot_into(snapshot.as_slice(), SparseMemory::default()).unwrap();
+        assert_eq!(restored.read_memory(1 << 40), 3);
+        assert_eq!(restored.memory().len(), program.memory().len());
+    }
+
+    #[test]
+    fn last_address_round_trip() {
+        let mut program = Program::init(&[99]);
+        program.set_memory(usize::MAX, 7);
+        assert!(save(&program).ends_with("\nmemory 0 99\nmemory 18446744073709551615 7\n"));
+        let restored = Program::load_snapshot(save(&program).as_bytes()).unwrap();
+        assert_eq!(restored.read_memory(usize::MAX), 7);
+        let mut program = Program::with_memory(SparseMemory::from_code(&[99]));
+        program.set_memory(usize::MAX - 1, 8);
+        assert!(save(&program).ends_with("\nmemory 0 99\nmemory 18446744073709551614 8,0\n"));
+        let restored = Program::load_snapshot_into(save(&program).as_bytes(), SparseMemory::default()).unwrap();
+        assert_eq!(restored.read_memory(usize::MAX - 1), 8);
+        assert_eq!(restored.memory().len(), usize::MAX);
+    }
+
+    #[test]
+    fn wide_values_round_trip() {
+        let mut program = Program::init(&[99]);
+        program.set_overflow_policy(OverflowPolicy::Wide);
+        program.set_wide_memory(3, 1 << 64);
+        assert!(save(&program).ends_with("\noverflow_policy wide\nmemory 0 99,0,0,18446744073709551616\n"));
+        let restored = Program::load_snapshot(save(&program).as_bytes()).unwrap();
+        assert_eq!(restored.overflow_policy(), OverflowPolicy::Wide);
+        assert_eq!(restored.read_wide_memory(3), 1 << 64);
+    }
+
+    #[test]
+    fn invalid_snapshots() {
+        let load = |snapshot: &str| Program::load_snapshot(snapshot.as_bytes()).err().unwrap().to_string();
+        assert_eq!(load("1,2,3\n"), "line 1: not an intcode snapshot");
+        assert_eq!(load(""), "line 1: not an intcode snapshot");
+        assert_eq!(load("intcode-snapshot 7\n"), "unsupported snapshot version '7'");
+        assert_eq!(load("intcode-snapshot 1\nregister 3\n"), "line 2: unknown entry");
+        assert_eq!(load("intcode-snapshot 1\nmemory 0 1,x\n"), "line 2: invalid memory value");
+        assert_eq!(load("intcode-snapshot 3\noverflow_policy lax\n"), "line 2: unknown overflow policy");
+        assert_eq!(load("intcode-snapshot 0\n"), "unsupported snapshot version '0'");
+        assert_eq!(load("intcode-snapshot 1\ninput 5\n"), "line 2: unknown entry");
+        assert_eq!(load("intcode-snapshot 2\noverflow_policy wide\n"), "line 2: unknown entry");
+        assert_eq!(load("intcode-snapshot 3\nmemory 18446744073709551615 1,2\n"), "line 2: invalid memory address");
+    }
+}
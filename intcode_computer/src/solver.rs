@@ -0,0 +1,432 @@
+//! Searches for values to patch into a program's memory, so that running it produces a target value.
+//!
+//! Many programs compute their result as a linear function of the patched cells, e.g. `a * noun + verb + c`.
+//! This is detected by running the program on a few sample patches, then all matches are computed directly
+//! and verified by running the program. As only the samples are checked, this is a heuristic: values for which
+//! the program deviates from the linear function are missed. Otherwise, all combinations are tried on multiple threads.
+use crate::{Memory, Opcode, Program};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::thread;
+
+/// A memory cell to patch before running the program, together with the values to try.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Patch {
+    pub address: usize,
+    pub values: RangeInclusive<i64>,
+}
+
+impl Patch {
+    /// Number of values to try, None if it doesn't fit into 64 bits.
+    fn len(&self) -> Option<u64> {
+        if self.values.is_empty() {
+            return Some(0);
+        }
+        return u64::try_from(*self.values.end() as i128 - *self.values.start() as i128 + 1).ok();
+    }
+}
+
+/// Instructions a single run of `solve` may execute, runs exceeding it never match.
+pub const DEFAULT_MAX_STEPS: usize = 1_000_000;
+
+/// Combinations that are tried at most, either by running the program or by solving the linear function.
+pub const MAX_COMBINATIONS: u64 = 1 << 24;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SolveError {
+    /// There are more than `MAX_COMBINATIONS` to try.
+    TooManyCombinations,
+}
+
+impl fmt::Display for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SolveError::TooManyCombinations => write!(f, "more than {} combinations to try", MAX_COMBINATIONS),
+        }
+    }
+}
+
+impl Error for SolveError {}
+
+/// The value that is compared against the target after the program terminated.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Observed {
+    Memory(usize),
+    LastOutput,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Strategy {
+    /// Matches were computed from a linear function fitted to samples, those where the program isn't linear may be missing.
+    Linear,
+    /// All combinations were tried.
+    BruteForce,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Solution {
+    /// Patched values of all matches, in the order of the patches. Sorted in ascending order.
+    pub matches: Vec<Vec<i64>>,
+    pub strategy: Strategy,
+}
+
+struct Search<'a, M: Memory> {
+    program: &'a Program<M>,
+    patches: &'a [Patch],
+    observed: Observed,
+    max_steps: usize,
+}
+
+impl<'a, M: Memory> Search<'a, M> {
+    /// Runs a copy of the program with the given values patched in, without inputs.
+    /// Returns None if the program fails, needs an input, doesn't terminate within the step limit,
+    /// or there's no output to observe.
+    fn evaluate(&self, values: &[i64]) -> Option<i64> {
+        let mut program = self.program.clone();
+        for (patch, value) in self.patches.iter().zip(values) {
+            program.set_memory(patch.address, *value);
+        }
+        let mut last_output = None;
+        for _ in 0..self.max_steps {
+            match program.next_opcode().ok()? {
+                Opcode::Terminate => {
+                    return match self.observed {
+                        Observed::Memory(address) => Some(program.read_memory(address)),
+                        Observed::LastOutput => last_output,
+                    };
+                }
+                opcode if opcode.reads_input() => return None,
+                _ => (),
+            }
+            if let Some(output) = program.step(None).ok()? {
+                last_output = Some(output);
+            }
+        }
+        return None;
+    }
+
+    /// Offset and coefficients of the observed value as a linear function of the patched values,
+    /// relative to the start of their ranges. None if a sample doesn't fit the linear function.
+    fn linear_model(&self) -> Option<(i128, Vec<i128>)> {
+        let base: Vec<i64> = self.patches.iter().map(|patch| *patch.values.start()).collect();
+        let offset = self.evaluate(&base)? as i128;
+        let mut coefficients = Vec::new();
+        for (patch_id, patch) in self.patches.iter().enumerate() {
+            if patch.len() == Some(1) {
+                coefficients.push(0);
+                continue;
+            }
+            let mut values = base.clone();
+            values[patch_id] += 1;
+            coefficients.push(self.evaluate(&values)? as i128 - offset);
+        }
+        let predict = |values: &[i64]| -> i128 {
+            let mut prediction = offset;
+            for ((value, start), coefficient) in values.iter().zip(base.iter()).zip(coefficients.iter()) {
+                prediction += (*value as i128 - *start as i128) * coefficient;
+            }
+            return prediction;
+        };
+        let mut samples = Vec::new();
+        for (patch_id, patch) in self.patches.iter().enumerate() {
+            let mut values = base.clone();
+            values[patch_id] = *patch.values.end();
+            samples.push(values);
+        }
+        samples.push(self.patches.iter().map(|patch| *patch.values.end()).collect());
+        samples.push(
+            self.patches
+                .iter()
+                .map(|patch| ((*patch.values.start() as i128 + *patch.values.end() as i128) / 2) as i64)
+                .collect(),
+        );
+        samples.push(self.patches.iter().map(|patch| patch.values.start() + (patch.values.start() < patch.values.end()) as i64).collect());
+        for sample in samples {
+            if self.evaluate(&sample)? as i128 != predict(&sample) {
+                return None;
+            }
+        }
+        return Some((offset, coefficients));
+    }
+
+    /// Solves the linear model for all patched values within their ranges.
+    /// None if there are more than `MAX_COMBINATIONS` of the other patches to iterate over.
+    fn solve_linear(&self, offset: i128, coefficients: &[i128], target: i64) -> Option<Vec<Vec<i64>>> {
+        // iterate over all patches but one, and compute the value of that one
+        let solved_id = (0..self.patches.len())
+            .filter(|patch_id| coefficients[*patch_id] != 0)
+            .max_by_key(|patch_id| self.patches[*patch_id].len().unwrap_or(u64::MAX));
+        let mut matches = Vec::new();
+        let iterated: Vec<Patch> = self
+            .patches
+            .iter()
+            .enumerate()
+            .map(|(patch_id, patch)| match Some(patch_id) == solved_id {
+                true => Patch { address: patch.address, values: *patch.values.start()..=*patch.values.start() },
+                false => patch.clone(),
+            })
+            .collect();
+        let count = combination_count(&iterated).filter(|count| *count <= MAX_COMBINATIONS)?;
+        for index in 0..count {
+            let mut values = combination(&iterated, index);
+            let mut remainder = target as i128 - offset;
+            for (patch_id, value) in values.iter().enumerate() {
+                if Some(patch_id) != solved_id {
+                    remainder -= (*value as i128 - *self.patches[patch_id].values.start() as i128) * coefficients[patch_id];
+                }
+            }
+            match solved_id {
+                Some(solved_id) => {
+                    if remainder % coefficients[solved_id] != 0 {
+                        continue;
+                    }
+                    let value = *self.patches[solved_id].values.start() as i128 + remainder / coefficients[solved_id];
+                    if value < *self.patches[solved_id].values.start() as i128 || value > *self.patches[solved_id].values.end() as i128 {
+                        continue;
+                    }
+                    values[solved_id] = value as i64;
+                }
+                None if remainder != 0 => continue,
+                None => (),
+            }
+            matches.push(values);
+        }
+        return Some(matches);
+    }
+}
+
+impl<'a, M: Memory + Sync> Search<'a, M> {
+    /// Tries the given number of combinations, spread over all available cores.
+    fn brute_force(&self, target: i64, total: u64) -> Vec<Vec<i64>> {
+        let thread_count = thread::available_parallelism().map_or(1, |count| count.get()) as u64;
+        let mut matches: Vec<Vec<i64>> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..thread_count)
+                .map(|thread_id| {
+                    scope.spawn(move || {
+                        let mut matches = Vec::new();
+                        let mut index = thread_id;
+                        while index < total {
+                            let values = combination(self.patches, index);
+                            if self.evaluate(&values) == Some(target) {
+                                matches.push(values);
+                            }
+                            index += thread_count;
+                        }
+                        return matches;
+                    })
+                })
+                .collect();
+            return handles.into_iter().flat_map(|handle| handle.join().expect("solver thread panicked")).collect();
+        });
+        matches.sort();
+        return matches;
+    }
+}
+
+/// None if the number of combinations doesn't fit into 64 bits.
+fn combination_count(patches: &[Patch]) -> Option<u64> {
+    return patches.iter().try_fold(1u64, |count, patch| count.checked_mul(patch.len()?));
+}
+
+/// The combination with the given index, the last patch changes fastest.
+/// The number of combinations must fit into 64 bits.
+fn combination(patches: &[Patch], mut index: u64) -> Vec<i64> {
+    let mut values = vec![0; patches.len()];
+    for (patch_id, patch) in patches.iter().enumerate().rev() {
+        let len = patch.len().expect("too many values to patch");
+        values[patch_id] = (*patch.values.start() as i128 + (index % len) as i128) as i64;
+        index /= len;
+    }
+    return values;
+}
+
+/// Finds combinations of patched values for which the observed value equals the target, all of them unless
+/// the `Strategy::Linear` heuristic was used. The program is run without inputs, runs that fail or exceed
+/// `DEFAULT_MAX_STEPS` never match.
+pub fn solve<M: Memory + Sync>(
+    program: &Program<M>,
+    patches: &[Patch],
+    observed: Observed,
+    target: i64,
+) -> Result<Solution, SolveError> {
+    return solve_with_limit(program, patches, observed, target, DEFAULT_MAX_STEPS);
+}
+
+/// Like `solve`, but runs that execute more than `max_steps` instructions never match.
+pub fn solve_with_limit<M: Memory + Sync>(
+    program: &Program<M>,
+    patches: &[Patch],
+    observed: Observed,
+    target: i64,
+    max_steps: usize,
+) -> Result<Solution, SolveError> {
+    let search = Search { program, patches, observed, max_steps };
+    let count = combination_count(patches);
+    if count == Some(0) {
+        return Ok(Solution { matches: Vec::new(), strategy: Strategy::BruteForce });
+    }
+    if let Some((offset, coefficients)) = search.linear_model() {
+        // without any verified match, the samples may have fit the model by chance
+        let matches = search.solve_linear(offset, &coefficients, target).unwrap_or_default();
+        if !matches.is_empty() && matches.iter().all(|values| search.evaluate(values) == Some(target)) {
+            return Ok(Solution { matches, strategy: Strategy::Linear });
+        }
+    }
+    return match count {
+        Some(count) if count <= MAX_COMBINATIONS => {
+            Ok(Solution { matches: search.brute_force(target, count), strategy: Strategy::BruteForce })
+        }
+        _ => Err(SolveError::TooManyCombinations),
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+
+    fn patches(addresses: [usize; 2], values: RangeInclusive<i64>) -> [Patch; 2] {
+        return [
+            Patch { address: addresses[0], values: values.clone() },
+            Patch { address: addresses[1], values },
+        ];
+    }
+
+    #[test]
+    fn linear_programs_are_solved_directly() {
+        // [0] = 3 * [a] + [b] + 7
+        let source = "
+                    MUL [a], #3, [0]
+                    ADD [0], [b], [0]
+                    ADD [0], #7, [0]
+                    HLT
+            a:      .data 0
+            b:      .data 0
+        ";
+        let program = Program::init(&assemble(source).unwrap());
+        let solution = solve(&program, &patches([13, 14], 0..=99), Observed::Memory(0), 40).unwrap();
+        assert_eq!(solution.strategy, Strategy::Linear);
+        let expected: Vec<Vec<i64>> = (0..=11).map(|a| vec![a, 33 - 3 * a]).collect();
+        assert_eq!(solution.matches, expected);
+    }
+
+    #[test]
+    fn non_linear_programs_are_brute_forced() {
+        // outputs [a] * [b]
+        let source = "
+                    MUL [a], [b], [c]
+                    OUT [c]
+                    HLT
+            a:      .data 0
+            b:      .data 0
+            c:      .data 0
+        ";
+        let program = Program::init(&assemble(source).unwrap());
+        let solution = solve(&program, &patches([7, 8], 0..=99), Observed::LastOutput, 12).unwrap();
+        assert_eq!(solution.strategy, Strategy::BruteForce);
+        assert_eq!(solution.matches, [vec![1, 12], vec![2, 6], vec![3, 4], vec![4, 3], vec![6, 2], vec![12, 1]]);
+    }
+
+    #[test]
+    fn failing_runs_never_match() {
+        let source = "
+                    JNZ [a], #bad
+                    HLT
+            bad:    .data 42
+            a:      .data 0
+            b:      .data 0
+        ";
+        let program = Program::init(&assemble(source).unwrap());
+        let solution = solve(&program, &patches([5, 6], 0..=2), Observed::Memory(6), 1).unwrap();
+        assert_eq!(solution.matches, [vec![0, 1]]);
+        assert_eq!(solution.strategy, Strategy::BruteForce);
+    }
+
+    #[test]
+    fn linear_samples_without_solution_are_brute_forced() {
+        // outputs [a], except for 50, where it outputs 1000
+        let source = "
+                    EQ [a], #50, [c]
+                    JNZ [c], #special
+                    OUT [a]
+                    HLT
+            special: OUT #1000
+                    HLT
+            a:      .data 0
+            c:      .data 0
+        ";
+        let program = Program::init(&assemble(source).unwrap());
+        let patches = [Patch { address: 13, values: 0..=99 }];
+        let solution = solve(&program, &patches, Observed::LastOutput, 1000).unwrap();
+        assert_eq!(solution.matches, [vec![50]]);
+        assert_eq!(solution.strategy, Strategy::BruteForce);
+    }
+
+    #[test]
+    fn linear_heuristic_misses_non_linear_matches() {
+        // outputs [a], except for 50, where it outputs 40
+        let source = "
+                    EQ [a], #50, [c]
+                    JNZ [c], #special
+                    OUT [a]
+                    HLT
+            special: OUT #40
+                    HLT
+            a:      .data 0
+            c:      .data 0
+        ";
+        let program = Program::init(&assemble(source).unwrap());
+        let patches = [Patch { address: 13, values: 0..=99 }];
+        let solution = solve(&program, &patches, Observed::LastOutput, 40).unwrap();
+        assert_eq!(solution.matches, [vec![40]]);
+        assert_eq!(solution.strategy, Strategy::Linear);
+    }
+
+    #[test]
+    fn too_many_combinations() {
+        let huge = Patch { address: 5, values: 0..=i64::MAX };
+        let program = Program::init(&[99]);
+        assert_eq!(solve(&program, &[huge.clone(), huge], Observed::Memory(0), 99), Err(SolveError::TooManyCombinations));
+        // outputs [a] * [b]
+        let program = Program::init(&[2, 7, 8, 9, 4, 9, 99, 0, 0, 0]);
+        let patches = patches([7, 8], 0..=1 << 12);
+        assert_eq!(solve(&program, &patches, Observed::LastOutput, 12), Err(SolveError::TooManyCombinations));
+    }
+
+    #[test]
+    fn endless_runs_never_match() {
+        // outputs [a], except for 3, where it loops forever
+        let source = "
+                    EQ [a], #3, [c]
+            loop:   JNZ [c], #loop
+                    OUT [a]
+                    HLT
+            a:      .data 0
+            c:      .data 0
+        ";
+        let program = Program::init(&assemble(source).unwrap());
+        let patches = [Patch { address: 10, values: 0..=9 }];
+        let solution = solve_with_limit(&program, &patches, Observed::LastOutput, 3, 100).unwrap();
+        assert_eq!(solution.matches, Vec::<Vec<i64>>::new());
+        assert_eq!(solution.strategy, Strategy::BruteForce);
+        assert_eq!(solve_with_limit(&program, &patches, Observed::LastOutput, 5, 100).unwrap().matches, [vec![5]]);
+    }
+
+    #[test]
+    fn combinations() {
+        let patches = [Patch { address: 0, values: -1..=1 }, Patch { address: 1, values: 5..=6 }];
+        assert_eq!(combination_count(&patches), Some(6));
+        assert_eq!(combination(&patches, 0), [-1, 5]);
+        assert_eq!(combination(&patches, 3), [0, 6]);
+        assert_eq!(combination(&patches, 5), [1, 6]);
+        let empty = [Patch { address: 0, values: RangeInclusive::new(1, 0) }];
+        assert_eq!(solve(&Program::init(&[99]), &empty, Observed::Memory(0), 0).unwrap().matches.len(), 0);
+        let full = Patch { address: 0, values: i64::MIN..=i64::MAX };
+        assert_eq!(full.len(), None);
+        let half = Patch { address: 0, values: 0..=u32::MAX as i64 };
+        assert_eq!(combination_count(&[half.clone(), Patch { address: 1, values: 0..=0 }]), Some(1 << 32));
+        assert_eq!(combination_count(&[half.clone(), half]), None);
+    }
+}
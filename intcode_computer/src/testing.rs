@@ -0,0 +1,404 @@
+//! Support for randomized tests: a small random number generator, a generator for well-formed random programs,
+//! an independent reference interpreter and checks of invariants that should hold for every program.
+//!
+//! Random programs may overwrite their own code, loop forever or fail, all of that is compared against the
+//! reference interpreter for a limited number of steps.
+//!
+//! Only built for this crate's tests, or with the `testing` feature.
+use crate::assembler::assemble;
+use crate::disassembler::disassemble;
+use crate::{Opcode, Program};
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::ops::RangeInclusive;
+
+/// Deterministic xorshift64* generator, so failing cases can be reproduced from their seed.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // the state must never be zero
+        return Rng { state: seed ^ 0x9e37_79b9_7f4a_7c15 | 1 };
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        return self.state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+    }
+
+    /// Uniformly distributed in the inclusive range, up to a negligible bias.
+    pub fn range(&mut self, range: RangeInclusive<i64>) -> i64 {
+        let size = (*range.end() as i128 - *range.start() as i128 + 1) as u128;
+        return (*range.start() as i128 + (self.next_u64() as u128 % size) as i128) as i64;
+    }
+
+    pub fn chance(&mut self, percent: u64) -> bool {
+        return self.next_u64() % 100 < percent;
+    }
+
+    pub fn choose<T: Copy>(&mut self, items: &[T]) -> T {
+        return items[self.next_u64() as usize % items.len()];
+    }
+}
+
+const SCRATCH_SIZE: i64 = 16;
+
+/// Generates a program of the given number of instructions, followed by `HLT`.
+/// Every instruction is valid on its own, but may access any address around the code and jump anywhere.
+pub fn random_program(rng: &mut Rng, instruction_count: usize) -> Vec<i64> {
+    let opcodes = [
+        Opcode::Add,
+        Opcode::Mul,
+        Opcode::Input,
+        Opcode::Output,
+        Opcode::JumpIfTrue,
+        Opcode::JumpIfFalse,
+        Opcode::LessThan,
+        Opcode::Equals,
+        Opcode::RelativeBaseOffset,
+    ];
+    let mut instructions: Vec<Opcode> = (0..instruction_count)
+        .map(|_| if rng.chance(3) { Opcode::Terminate } else { rng.choose(&opcodes) })
+        .collect();
+    instructions.push(Opcode::Terminate);
+    let mut starts = Vec::new();
+    let mut length = 0;
+    for opcode in instructions.iter() {
+        starts.push(length as i64);
+        length += opcode.parameter_count() + 1;
+    }
+    let code_end = length as i64 + SCRATCH_SIZE;
+
+    let mut code = Vec::new();
+    for opcode in instructions {
+        let mut word = opcode.number();
+        let mut parameters = Vec::new();
+        for parameter_id in 1..=opcode.parameter_count() {
+            let is_result = opcode.result_parameter() == Some(parameter_id);
+            let is_jump_target = parameter_id == 2 && (opcode == Opcode::JumpIfTrue || opcode == Opcode::JumpIfFalse);
+            let mode = match (is_result, is_jump_target) {
+                (true, _) => rng.choose(&[0, 0, 2]),
+                (false, true) if rng.chance(80) => 1,
+                _ => rng.choose(&[0, 1, 2]),
+            };
+            let value = match mode {
+                0 => rng.range(0..=code_end),
+                1 if is_jump_target => rng.choose(&starts),
+                1 if opcode == Opcode::RelativeBaseOffset => rng.range(-5..=30),
+                1 => rng.range(-20..=20),
+                _ => rng.range(-4..=16),
+            };
+            word += mode * 10i64.pow(parameter_id as u32 + 1);
+            parameters.push(value);
+        }
+        // a mode digit for a parameter the opcode doesn't have, which execution ignores
+        if opcode.parameter_count() < 3 && rng.chance(10) {
+            let parameter_id = rng.range(opcode.parameter_count() as i64 + 1..=3) as u32;
+            word += rng.range(1..=2) * 10i64.pow(parameter_id + 1);
+        }
+        code.push(word);
+        code.extend(parameters);
+    }
+    return code;
+}
+
+/// Why the reference interpreter stopped.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ReferenceEnd {
+    Halted,
+    /// The instruction at the instruction pointer can't be executed.
+    Failed,
+    /// The instruction at the instruction pointer overflows, so it fails with the default `OverflowPolicy::Checked`.
+    Overflow,
+    StepLimit,
+}
+
+/// Straightforward interpreter, sharing no code with `Program`.
+#[derive(Debug, Clone)]
+pub struct ReferenceMachine {
+    pub memory: Vec<i64>,
+    pub instruction_pointer: usize,
+    pub relative_base: i64,
+    pub inputs: VecDeque<i64>,
+    pub outputs: Vec<i64>,
+}
+
+impl ReferenceMachine {
+    fn read(&self, address: i64) -> i64 {
+        return *self.memory.get(address as usize).unwrap_or(&0);
+    }
+
+    fn word(&self, offset: usize) -> i64 {
+        return self.read((self.instruction_pointer + offset) as i64);
+    }
+
+    fn address(&self, parameter_id: usize, mode: i64) -> Result<i64, ReferenceEnd> {
+        let address = match mode {
+            0 => self.word(parameter_id),
+            2 => self.relative_base + self.word(parameter_id),
+            _ => return Err(ReferenceEnd::Failed),
+        };
+        return if address < 0 { Err(ReferenceEnd::Failed) } else { Ok(address) };
+    }
+
+    fn value(&self, parameter_id: usize, mode: i64) -> Result<i64, ReferenceEnd> {
+        return match mode {
+            1 => Ok(self.word(parameter_id)),
+            _ => Ok(self.read(self.address(parameter_id, mode)?)),
+        };
+    }
+
+    fn jump_target(&self, parameter_id: usize, mode: i64) -> Result<usize, ReferenceEnd> {
+        return usize::try_from(self.value(parameter_id, mode)?).map_err(|_| ReferenceEnd::Failed);
+    }
+
+    fn write(&mut self, address: i64, value: i64) {
+        let address = address as usize;
+        if address >= self.memory.len() {
+            self.memory.resize(address + 1, 0);
+        }
+        self.memory[address] = value;
+    }
+
+    /// Executes one instruction. On failure, nothing is changed.
+    fn step(&mut self) -> Result<(), ReferenceEnd> {
+        let word = self.word(0);
+        let modes = [word / 100 % 10, word / 1000 % 10, word / 10000 % 10];
+        if modes.iter().any(|mode| !(0..=2).contains(mode)) || word < 0 {
+            return Err(ReferenceEnd::Failed);
+        }
+        let arithmetic = |machine: &ReferenceMachine, operation: fn(i64, i64) -> Option<i64>| -> Result<(i64, i64), ReferenceEnd> {
+            let first = machine.value(1, modes[0])?;
+            let second = machine.value(2, modes[1])?;
+            let address = machine.address(3, modes[2])?;
+            return Ok((address, operation(first, second).ok_or(ReferenceEnd::Overflow)?));
+        };
+        match word % 100 {
+            1 | 2 | 7 | 8 => {
+                let operation: fn(i64, i64) -> Option<i64> = match word % 100 {
+                    1 => |a, b| a.checked_add(b),
+                    2 => |a, b| a.checked_mul(b),
+                    7 => |a, b| Some((a < b) as i64),
+                    _ => |a, b| Some((a == b) as i64),
+                };
+                let (address, value) = arithmetic(self, operation)?;
+                self.write(address, value);
+                self.instruction_pointer += 4;
+            }
+            3 => {
+                let address = self.address(1, modes[0])?;
+                let input = self.inputs.pop_front().ok_or(ReferenceEnd::Failed)?;
+                self.write(address, input);
+                self.instruction_pointer += 2;
+            }
+            4 => {
+                let value = self.value(1, modes[0])?;
+                self.outputs.push(value);
+                self.instruction_pointer += 2;
+            }
+            5 | 6 => {
+                let condition = self.value(1, modes[0])?;
+                let target = self.jump_target(2, modes[1])?;
+                if (condition != 0) == (word % 100 == 5) {
+                    self.instruction_pointer = target;
+                } else {
+                    self.instruction_pointer += 3;
+                }
+            }
+            9 => {
+                let relative_base = self.relative_base + self.value(1, modes[0])?;
+                if relative_base < 0 {
+                    return Err(ReferenceEnd::Failed);
+                }
+                self.relative_base = relative_base;
+                self.instruction_pointer += 2;
+            }
+            99 => return Err(ReferenceEnd::Halted),
+            _ => return Err(ReferenceEnd::Failed),
+        }
+        return Ok(());
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReferenceRun {
+    pub machine: ReferenceMachine,
+    /// Number of instructions executed successfully.
+    pub steps: usize,
+    pub end: ReferenceEnd,
+}
+
+/// Runs the code on the reference interpreter, for at most `max_steps` instructions.
+pub fn reference_run(code: &[i64], inputs: &[i64], max_steps: usize) -> ReferenceRun {
+    let mut machine = ReferenceMachine {
+        memory: code.to_vec(),
+        instruction_pointer: 0,
+        relative_base: 0,
+        inputs: inputs.iter().cloned().collect(),
+        outputs: Vec::new(),
+    };
+    for steps in 0..max_steps {
+        if let Err(end) = machine.step() {
+            return ReferenceRun { machine, steps, end };
+        }
+    }
+    return ReferenceRun { machine, steps: max_steps, end: ReferenceEnd::StepLimit };
+}
+
+/// Executes the given number of steps, taking inputs from the queue. Stops at the first failure.
+fn run_steps(program: &mut Program, inputs: &mut VecDeque<i64>, outputs: &mut Vec<i64>, steps: usize) -> Result<(), String> {
+    for _ in 0..steps {
+        let input = match program.next_opcode() {
+            Ok(opcode) if opcode.reads_input() => inputs.pop_front(),
+            _ => None,
+        };
+        if let Some(output) = program.step(input).map_err(|error| error.to_string())? {
+            outputs.push(output);
+        }
+    }
+    return Ok(());
+}
+
+fn compare(what: &str, program: &Program, outputs: &[i64], machine: &ReferenceMachine) -> Result<(), String> {
+    if program.instruction_pointer() != machine.instruction_pointer
+        || program.relative_base() as i64 != machine.relative_base
+        || outputs != &machine.outputs[..]
+        || program.memory_as_vec() != machine.memory
+    {
+        return Err(format!(
+            "{}: program at #{} (rb {}) with outputs {:?}, reference at #{} (rb {}) with outputs {:?}",
+            what,
+            program.instruction_pointer(),
+            program.relative_base(),
+            outputs,
+            machine.instruction_pointer,
+            machine.relative_base,
+            machine.outputs
+        ));
+    }
+    return Ok(());
+}
+
+/// Disassembling and assembling the code again gives the same code.
+pub fn check_round_trip(code: &[i64]) -> Result<(), String> {
+    let source: Vec<String> = disassemble(code).iter().map(|line| line.decoded.to_string()).collect();
+    let assembled = assemble(&source.join("\n")).map_err(|error| format!("round trip: {}", error))?;
+    if assembled != code {
+        return Err(format!("round trip: {:?} became {:?}", code, assembled));
+    }
+    return Ok(());
+}
+
+/// Stepping through the program agrees with the reference interpreter, including where it fails.
+/// If the program halts within `max_steps`, `run` agrees as well.
+pub fn check_against_reference(code: &[i64], inputs: &[i64], max_steps: usize) -> Result<(), String> {
+    let reference = reference_run(code, inputs, max_steps);
+    let mut program = Program::init(code);
+    let mut queue: VecDeque<i64> = inputs.iter().cloned().collect();
+    let mut outputs = Vec::new();
+    run_steps(&mut program, &mut queue, &mut outputs, reference.steps)
+        .map_err(|error| format!("failed after less than {} steps: {}", reference.steps, error))?;
+    compare("step by step", &program, &outputs, &reference.machine)?;
+    match reference.end {
+        ReferenceEnd::Failed | ReferenceEnd::Overflow => {
+            let before = program.memory_as_vec();
+            if run_steps(&mut program, &mut queue, &mut outputs, 1).is_ok() {
+                return Err(format!("instruction #{} should fail", reference.machine.instruction_pointer));
+            }
+            if program.memory_as_vec() != before {
+                return Err("failed step changed memory".to_string());
+            }
+            compare("after failure", &program, &outputs, &reference.machine)?;
+        }
+        ReferenceEnd::Halted => {
+            if !program.will_terminate() {
+                return Err("program should terminate".to_string());
+            }
+            let mut run_program = Program::init(code);
+            let run_outputs = run_program.run(inputs.iter().rev().cloned().collect()).map_err(|error| error.to_string())?;
+            compare("run", &run_program, &run_outputs, &reference.machine)?;
+        }
+        ReferenceEnd::StepLimit => (),
+    }
+    return Ok(());
+}
+
+/// Saving a snapshot halfway and restoring it continues exactly like the original program.
+pub fn check_snapshot(code: &[i64], inputs: &[i64], max_steps: usize) -> Result<(), String> {
+    let reference = reference_run(code, inputs, max_steps);
+    let mut program = Program::init(code);
+    let mut queue: VecDeque<i64> = inputs.iter().cloned().collect();
+    let mut outputs = Vec::new();
+    run_steps(&mut program, &mut queue, &mut outputs, reference.steps / 2)?;
+    let mut snapshot = Vec::new();
+    program.save_snapshot(&mut snapshot).map_err(|error| error.to_string())?;
+    let mut restored = Program::load_snapshot(snapshot.as_slice()).map_err(|error| error.to_string())?;
+    let mut restored_queue = queue.clone();
+    let mut restored_outputs = outputs.clone();
+    let remaining_steps = reference.steps - reference.steps / 2;
+    run_steps(&mut program, &mut queue, &mut outputs, remaining_steps)?;
+    run_steps(&mut restored, &mut restored_queue, &mut restored_outputs, remaining_steps)?;
+    compare("restored snapshot", &restored, &restored_outputs, &reference.machine)?;
+    return compare("original of snapshot", &program, &outputs, &reference.machine);
+}
+
+/// All invariants above.
+pub fn check_invariants(code: &[i64], inputs: &[i64], max_steps: usize) -> Result<(), String> {
+    check_round_trip(code)?;
+    check_against_reference(code, inputs, max_steps)?;
+    return check_snapshot(code, inputs, max_steps);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rng_is_deterministic() {
+        let mut first = Rng::new(7);
+        let mut second = Rng::new(7);
+        let values: Vec<i64> = (0..100).map(|_| first.range(-3..=3)).collect();
+        assert_eq!(values, (0..100).map(|_| second.range(-3..=3)).collect::<Vec<i64>>());
+        assert!(values.iter().all(|value| (-3..=3).contains(value)));
+        assert!((-3..=3).all(|value| values.contains(&value)));
+    }
+
+    #[test]
+    fn reference_interpreter() {
+        let code = [109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99];
+        let reference = reference_run(&code, &[], 1000);
+        assert_eq!(reference.end, ReferenceEnd::Halted);
+        assert_eq!(reference.machine.outputs, code);
+        let reference = reference_run(&[1101, 1, 2, 0, 1105, 1, 0], &[], 10);
+        assert_eq!(reference.end, ReferenceEnd::Failed);
+        assert_eq!(reference_run(&[1105, 1, 0], &[], 10).end, ReferenceEnd::StepLimit);
+        assert_eq!(reference_run(&[2, 7, 7, 7, 1105, 1, 0, 3], &[], 100).end, ReferenceEnd::Overflow);
+    }
+
+    #[test]
+    fn unused_mode_digits_survive() {
+        for code in [vec![10004, 5, 99], vec![1199], vec![1101, 2, 3, 7, 21004, 7, 99, 0]].iter() {
+            if let Err(error) = check_invariants(code, &[], 100) {
+                panic!("{}\ncode: {:?}", error, code);
+            }
+        }
+    }
+
+    #[test]
+    fn random_programs_satisfy_invariants() {
+        for seed in 0..500 {
+            let mut rng = Rng::new(seed);
+            let instruction_count = rng.range(1..=24) as usize;
+            let code = random_program(&mut rng, instruction_count);
+            let inputs: Vec<i64> = (0..16).map(|_| rng.range(-10..=10)).collect();
+            if let Err(error) = check_invariants(&code, &inputs, 500) {
+                panic!("seed {}: {}\ncode: {:?}", seed, error, code);
+            }
+        }
+    }
+}
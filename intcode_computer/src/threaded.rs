@@ -0,0 +1,106 @@
+//! Runs programs on their own threads, communicating through channels.
+use crate::{IntcodeError, Memory, PagedMemory, Program};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+/// Runs the program on a new thread until it terminates.
+/// Input instructions block until a value is received, outputs are sent as soon as they are produced.
+/// Outputs are dropped silently once the receiving end is gone, e.g. because the next program in a feedback loop terminated.
+/// The join handle yields the terminated program, or the error that stopped it.
+/// If the input channel is closed while the program waits for input, it fails with `ErrorReason::MissingInput`.
+pub fn spawn<M: Memory + Send + 'static>(
+    mut program: Program<M>,
+    input: Receiver<i64>,
+    output: Sender<i64>,
+) -> JoinHandle<Result<Program<M>, IntcodeError>> {
+    return thread::spawn(move || {
+        program.run_with(&mut (input, output))?;
+        return Ok(program);
+    });
+}
+
+/// A program running on its own thread, together with the channels to talk to it.
+pub struct ProgramThread<M: Memory = PagedMemory> {
+    pub input: Sender<i64>,
+    pub output: Receiver<i64>,
+    handle: JoinHandle<Result<Program<M>, IntcodeError>>,
+}
+
+impl<M: Memory + Send + 'static> ProgramThread<M> {
+    pub fn spawn(program: Program<M>) -> ProgramThread<M> {
+        let (input, program_input) = channel();
+        let (program_output, output) = channel();
+        return ProgramThread {
+            input,
+            output,
+            handle: spawn(program, program_input, program_output),
+        };
+    }
+
+    /// Waits for the program to terminate.
+    pub fn join(self) -> Result<Program<M>, IntcodeError> {
+        drop(self.input);
+        return self.handle.join().expect("program thread panicked");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::ErrorReason;
+
+    #[test]
+    fn program_thread_echoes_inputs() {
+        let source = "
+            loop:   IN [x]
+                    JZ [x], #end
+                    ADD [x], #1, [x]
+                    OUT [x]
+                    JNZ #1, #loop
+            end:    HLT
+            x:      .data 0
+        ";
+        let program_thread = ProgramThread::spawn(Program::init(&assemble(source).unwrap()));
+        for value in [4, 41, -3].iter() {
+            program_thread.input.send(*value).unwrap();
+            assert_eq!(program_thread.output.recv().unwrap(), value + 1);
+        }
+        program_thread.input.send(0).unwrap();
+        let program = program_thread.join().unwrap();
+        assert!(program.will_terminate());
+        assert_eq!(program.read_memory(15), 0);
+    }
+
+    #[test]
+    fn closed_input_fails() {
+        let program_thread = ProgramThread::spawn(Program::init(&[3, 0, 99]));
+        let error = program_thread.join().err().unwrap();
+        assert_eq!(error.instruction_pointer, 0);
+        assert_eq!(error.reason, ErrorReason::MissingInput);
+    }
+
+    #[test]
+    fn programs_in_a_feedback_loop() {
+        // doubles its input until it exceeds 100
+        let source = "
+            loop:   IN [x]
+                    MUL [x], #2, [x]
+                    OUT [x]
+                    LT [x], #100, [small]
+                    JNZ [small], #loop
+                    HLT
+            x:      .data 0
+            small:  .data 0
+        ";
+        let code = assemble(source).unwrap();
+        let (first_sender, first_receiver) = channel();
+        let (second_sender, second_receiver) = channel();
+        first_sender.send(1).unwrap();
+        let first = spawn(Program::init(&code), first_receiver, second_sender);
+        let second = spawn(Program::init(&code), second_receiver, first_sender);
+        assert_eq!(first.join().unwrap().unwrap().read_memory(16), 128);
+        // the last output of the second program is dropped, as the first one already terminated
+        assert_eq!(second.join().unwrap().unwrap().read_memory(16), 256);
+    }
+}
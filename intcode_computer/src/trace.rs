@@ -0,0 +1,269 @@
+use crate::disassembler::{Decoded, Parameter};
+use crate::{Memory, Opcode, ParameterMode, Program};
+use std::fmt;
+
+/// A single memory cell written by an instruction. Values are wide, see `Program::read_wide_memory`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MemoryWrite {
+    pub address: usize,
+    pub old_value: i128,
+    pub new_value: i128,
+}
+
+/// Everything a single executed instruction did.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TraceEntry {
+    pub instruction_pointer: usize,
+    pub opcode: Opcode,
+    /// Parameters as they are stored in memory, together with their modes.
+    pub parameters: Vec<Parameter>,
+    /// Values of all parameters that are read, after resolving their modes.
+    /// Wide, as `OverflowPolicy::Wide` lets arithmetic and comparisons read values that don't fit into 64 bits.
+    pub operands: Vec<i128>,
+    pub writes: Vec<MemoryWrite>,
+    /// Old and new relative base, if the instruction changed it.
+    pub relative_base: Option<(usize, usize)>,
+    pub input: Option<i64>,
+    pub output: Option<i64>,
+}
+
+impl TraceEntry {
+    /// Inspects the instruction the program is about to execute.
+    /// Returns None if the instruction can't be decoded or a parameter can't be resolved, as executing it will fail anyway.
+    /// Custom opcodes don't necessarily resolve all their parameters, those that can't be resolved are left out.
+    pub(crate) fn before_step<M: Memory>(program: &Program<M>) -> Option<TraceEntry> {
+        let instruction_pointer = program.instruction_pointer;
+        let (opcode, pm1, pm2, pm3) = program.peek_instruction().ok()?;
+        let modes = [pm1, pm2, pm3];
+        let mut parameters = Vec::new();
+        let mut operands = Vec::new();
+        let mut writes = Vec::new();
+        for parameter_id in 1..=opcode.parameter_count() {
+            let mode = modes[parameter_id - 1];
+            parameters.push(Parameter {
+                mode,
+                value: program.read_memory(instruction_pointer + parameter_id),
+            });
+            let is_custom = matches!(opcode, Opcode::Custom(_));
+            if opcode.result_parameter() == Some(parameter_id) {
+                match program.resolve_parameter_to_result_address(parameter_id, mode) {
+                    Ok(address) => {
+                        let old_value = program.read_wide_memory(address);
+                        writes.push(MemoryWrite { address, old_value, new_value: old_value });
+                    }
+                    Err(_) if is_custom => (),
+                    Err(_) => return None,
+                }
+            } else {
+                match program.resolve_parameter_to_wide_value(parameter_id, mode) {
+                    Ok(operand) => operands.push(operand),
+                    Err(_) if is_custom => (),
+                    Err(_) => return None,
+                }
+            }
+        }
+        return Some(TraceEntry {
+            instruction_pointer,
+            opcode,
+            parameters,
+            operands,
+            writes,
+            relative_base: Some((program.relative_base, program.relative_base)),
+            input: None,
+            output: None,
+        });
+    }
+
+    /// Completes the entry with the effects of the executed instruction.
+    pub(crate) fn after_step<M: Memory>(&mut self, program: &Program<M>, input: Option<i64>, output: Option<i64>) {
+        for write in self.writes.iter_mut() {
+            write.new_value = program.read_wide_memory(write.address);
+        }
+        self.relative_base = match self.relative_base {
+            Some((old, _)) if old != program.relative_base => Some((old, program.relative_base)),
+            _ => None,
+        };
+        if self.opcode.reads_input() {
+            self.input = input;
+        }
+        self.output = output;
+    }
+
+    pub fn to_json(&self) -> String {
+        let modes: Vec<String> = self.parameters.iter().map(|p| format!("\"{}\"", mode_name(p.mode))).collect();
+        let parameters: Vec<String> = self.parameters.iter().map(|p| p.value.to_string()).collect();
+        let operands: Vec<String> = self.operands.iter().map(|o| o.to_string()).collect();
+        let writes: Vec<String> = self
+            .writes
+            .iter()
+            .map(|w| format!("{{\"address\":{},\"old\":{},\"new\":{}}}", w.address, w.old_value, w.new_value))
+            .collect();
+        let relative_base = match self.relative_base {
+            Some((old, new)) => format!("{{\"old\":{},\"new\":{}}}", old, new),
+            None => "null".to_string(),
+        };
+        let optional = |value: Option<i64>| value.map_or("null".to_string(), |v| v.to_string());
+        return format!(
+            "{{\"ip\":{},\"opcode\":\"{}\",\"modes\":[{}],\"parameters\":[{}],\"operands\":[{}],\"writes\":[{}],\"relative_base\":{},\"input\":{},\"output\":{}}}",
+            self.instruction_pointer,
+            self.opcode.mnemonic(),
+            modes.join(","),
+            parameters.join(","),
+            operands.join(","),
+            writes.join(","),
+            relative_base,
+            optional(self.input),
+            optional(self.output),
+        );
+    }
+}
+
+/// Compact, human-readable form: the disassembled instruction followed by its effects.
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let instruction = Decoded::Instruction {
+            opcode: self.opcode,
+            parameters: self.parameters.clone(),
+        };
+        write!(f, "{:>6}: {:<28}", self.instruction_pointer, instruction.to_string())?;
+        if !self.operands.is_empty() {
+            let operands: Vec<String> = self.operands.iter().map(|o| o.to_string()).collect();
+            write!(f, " | {}", operands.join(" "))?;
+        }
+        for write in self.writes.iter() {
+            write!(f, " | [{}] {} -> {}", write.address, write.old_value, write.new_value)?;
+        }
+        if let Some((old, new)) = self.relative_base {
+            write!(f, " | rb {} -> {}", old, new)?;
+        }
+        if let Some(input) = self.input {
+            write!(f, " | in {}", input)?;
+        }
+        if let Some(output) = self.output {
+            write!(f, " | out {}", output)?;
+        }
+        return Ok(());
+    }
+}
+
+fn mode_name(mode: ParameterMode) -> &'static str {
+    return match mode {
+        ParameterMode::Position => "position",
+        ParameterMode::Immediate => "immediate",
+        ParameterMode::Relative => "relative",
+    };
+}
+
+/// All instructions executed by a program since tracing was enabled, in order.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Trace {
+    pub entries: Vec<TraceEntry>,
+}
+
+impl Trace {
+    /// One JSON object per executed instruction and line.
+    pub fn to_json_lines(&self) -> String {
+        let mut json_lines = String::new();
+        for entry in self.entries.iter() {
+            json_lines += &entry.to_json();
+            json_lines += "\n";
+        }
+        return json_lines;
+    }
+
+    /// One line per executed instruction, suitable for diffing two runs.
+    pub fn to_compact(&self) -> String {
+        let mut compact = String::new();
+        for entry in self.entries.iter() {
+            compact += &format!("{}\n", entry);
+        }
+        return compact;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::custom::{CustomAction, OpcodeDefinition};
+    use crate::{OverflowPolicy, Program, ProgramBuilder};
+
+    #[test]
+    fn tracing_is_opt_in() {
+        let mut program = Program::init(&[104, 0, 99]);
+        program.run(Vec::new()).unwrap();
+        assert!(program.trace().is_none());
+    }
+
+    #[test]
+    fn trace_records_effects() {
+        let mut program = Program::init(&[109, 19, 3, 0, 21101, 2, 3, -19, 204, -19, 99]);
+        program.enable_tracing();
+        assert_eq!(program.run(vec![7]).unwrap(), [5]);
+        let trace = program.take_trace().unwrap();
+        assert_eq!(trace.entries.len(), 4);
+        let arb = &trace.entries[0];
+        assert_eq!(arb.operands, [19]);
+        assert_eq!(arb.relative_base, Some((0, 19)));
+        let input = &trace.entries[1];
+        assert_eq!(input.input, Some(7));
+        assert_eq!(input.writes[0].address, 0);
+        assert_eq!(input.writes[0].old_value, 109);
+        assert_eq!(input.writes[0].new_value, 7);
+        assert_eq!(input.relative_base, None);
+        let add = &trace.entries[2];
+        assert_eq!(add.operands, [2, 3]);
+        assert_eq!(add.writes[0].address, 0);
+        assert_eq!(add.writes[0].new_value, 5);
+        assert_eq!(trace.entries[3].output, Some(5));
+        assert!(program.trace().is_none());
+    }
+
+    #[test]
+    fn trace_exports() {
+        let mut program = Program::init(&[1101, 100, -1, 4, 0]);
+        program.enable_tracing();
+        program.run(Vec::new()).unwrap();
+        let trace = program.trace().unwrap();
+        assert_eq!(
+            trace.to_json_lines(),
+            "{\"ip\":0,\"opcode\":\"ADD\",\"modes\":[\"immediate\",\"immediate\",\"position\"],\"parameters\":[100,-1,4],\"operands\":[100,-1],\"writes\":[{\"address\":4,\"old\":0,\"new\":99}],\"relative_base\":null,\"input\":null,\"output\":null}\n"
+        );
+        assert_eq!(
+            trace.to_compact(),
+            "     0: ADD #100, #-1, [4]           | 100 -1 | [4] 0 -> 99\n"
+        );
+    }
+
+    #[test]
+    fn wide_values_are_traced() {
+        // [9] = [8] * [8], [10] = [9] < [8]
+        let mut program = Program::init(&[2, 8, 8, 9, 7, 9, 8, 10, 1 << 40, 0, 0]);
+        program.set_overflow_policy(OverflowPolicy::Wide);
+        program.enable_tracing();
+        program.step(None).unwrap();
+        program.step(None).unwrap();
+        let trace = program.take_trace().unwrap();
+        assert_eq!(trace.entries.len(), 2);
+        assert_eq!(trace.entries[0].writes[0].new_value, 1 << 80);
+        assert_eq!(trace.entries[1].operands, [1 << 80, 1 << 40]);
+        assert_eq!(trace.entries[1].writes[0].new_value, 0);
+    }
+
+    #[test]
+    fn custom_opcodes_are_traced_with_unresolved_parameters() {
+        let ignore = OpcodeDefinition { number: 50, name: "IGN", parameter_count: 2, result_parameter: None, reads_input: false };
+        let mut program = ProgramBuilder::new(&[50, -1, 7, 99]).opcode(ignore, |_| Ok(CustomAction::Continue)).build();
+        program.enable_tracing();
+        program.run(Vec::new()).unwrap();
+        let trace = program.take_trace().unwrap();
+        assert_eq!(trace.entries.len(), 1);
+        assert_eq!(trace.entries[0].operands, [0]);
+    }
+
+    #[test]
+    fn failed_steps_are_not_traced() {
+        let mut program = Program::init(&[3, 0, 99]);
+        program.enable_tracing();
+        assert!(program.run(Vec::new()).is_err());
+        assert!(program.trace().unwrap().entries.is_empty());
+    }
+}
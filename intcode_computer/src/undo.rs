@@ -0,0 +1,192 @@
+//! Reverse execution, see `Program::enable_undo_log`.
+//!
+//! Only the program's own state is restored: memory, instruction pointer, relative base and inputs queued for `resume`.
+//! Traces, profiles and watchpoint callbacks aren't rolled back, and memory keeps its length.
+use crate::accesses::InstructionAccesses;
+use crate::{Memory, Program};
+use std::collections::VecDeque;
+
+/// The state an instruction changed, as it was before executing it.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct UndoEntry {
+    pub instruction_pointer: usize,
+    pub relative_base: usize,
+    /// Address and old value of the memory cell the instruction wrote to, see `OverflowPolicy::Wide` for values beyond 64 bits.
+    pub write: Option<(usize, i128)>,
+    /// Input consumed by an input instruction.
+    pub input: Option<i64>,
+    /// Whether the input was taken from the queue filled by `provide_input`, so it's queued again when stepping back.
+    pub input_was_queued: bool,
+}
+
+impl UndoEntry {
+    pub(crate) fn before_step<M: Memory>(program: &Program<M>, accesses: &InstructionAccesses, input: Option<i64>) -> UndoEntry {
+        return UndoEntry {
+            instruction_pointer: program.instruction_pointer,
+            relative_base: program.relative_base,
+            write: accesses.write.map(|address| (address, program.read_wide_memory(address))),
+            input: if accesses.opcode.reads_input() { input } else { None },
+            input_was_queued: false,
+        };
+    }
+}
+
+/// The most recent undo entries, up to a fixed number of steps.
+#[derive(Debug, Clone)]
+pub struct UndoLog {
+    pub entries: VecDeque<UndoEntry>,
+    capacity: usize,
+}
+
+impl UndoLog {
+    pub(crate) fn new(capacity: usize) -> UndoLog {
+        return UndoLog {
+            entries: VecDeque::new(),
+            capacity,
+        };
+    }
+
+    pub(crate) fn push(&mut self, entry: UndoEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+}
+
+impl<M: Memory> Program<M> {
+    /// Starts recording the effects of executed instructions, so they can be undone with `step_back`.
+    /// Only the last `capacity` instructions are kept. Clears the log if it was already enabled.
+    pub fn enable_undo_log(&mut self, capacity: usize) {
+        self.undo_log = Some(UndoLog::new(capacity));
+    }
+
+    pub fn undo_log(&self) -> Option<&UndoLog> {
+        return self.undo_log.as_ref();
+    }
+
+    /// Undoes the last executed instruction. Returns false if there is nothing (more) to undo.
+    /// Inputs passed to `step` or `run` directly aren't queued again, see `UndoEntry::input`.
+    pub fn step_back(&mut self) -> bool {
+        let entry = match self.undo_log.as_mut().and_then(|log| log.entries.pop_back()) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        if let Some((address, old_value)) = entry.write {
+            self.set_wide_memory(address, old_value);
+        }
+        if let (Some(input), true) = (entry.input, entry.input_was_queued) {
+            self.pending_inputs.push_front(input);
+        }
+        self.instruction_pointer = entry.instruction_pointer;
+        self.relative_base = entry.relative_base;
+        return true;
+    }
+
+    /// Steps back at least once, then until the instruction pointer is at a breakpoint.
+    /// Returns false if the undo log ran out before reaching a breakpoint.
+    pub fn reverse_continue(&mut self, is_breakpoint: impl Fn(usize) -> bool) -> bool {
+        if !self.step_back() {
+            return false;
+        }
+        while !is_breakpoint(self.instruction_pointer) {
+            if !self.step_back() {
+                return false;
+            }
+        }
+        return true;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::custom::{CustomAction, OpcodeDefinition};
+    use crate::{ProgramBuilder, RunState};
+
+    // counts [13] down from the input, outputting every number
+    const COUNTDOWN: [i64; 14] = [3, 13, 4, 13, 1001, 13, -1, 13, 1005, 13, 2, 99, 0, 0];
+
+    #[test]
+    fn step_back_restores_state() {
+        let mut program = Program::init(&[109, 7, 21101, 2, 3, 1, 99]);
+        program.enable_undo_log(10);
+        let original = program.memory_as_vec();
+        program.run(Vec::new()).unwrap();
+        assert_eq!(program.read_memory(8), 5);
+        assert!(program.step_back());
+        assert_eq!(program.instruction_pointer(), 2);
+        assert_eq!(program.read_memory(8), 0);
+        assert!(program.step_back());
+        assert_eq!(program.relative_base(), 0);
+        assert!(!program.step_back());
+        assert_eq!(program.memory_as_vec()[..original.len()], original[..]);
+    }
+
+    #[test]
+    fn replay_after_step_back() {
+        let mut program = Program::init(&COUNTDOWN);
+        program.enable_undo_log(100);
+        assert_eq!(program.run(vec![3]).unwrap(), [3, 2, 1]);
+        assert_eq!(program.undo_log().unwrap().entries[0].input, Some(3));
+        while program.step_back() {}
+        assert_eq!(program.instruction_pointer(), 0);
+        assert_eq!(program.run(vec![2]).unwrap(), [2, 1]);
+    }
+
+    #[test]
+    fn reverse_continue_to_breakpoint() {
+        let mut program = Program::init(&COUNTDOWN);
+        program.enable_undo_log(100);
+        program.run(vec![3]).unwrap();
+        // back to the last output, which printed 1
+        assert!(program.reverse_continue(|address| address == 2));
+        assert_eq!(program.read_memory(13), 1);
+        assert!(program.reverse_continue(|address| address == 2));
+        assert_eq!(program.read_memory(13), 2);
+        assert!(!program.reverse_continue(|address| address == 11));
+        assert_eq!(program.instruction_pointer(), 0);
+    }
+
+    #[test]
+    fn capacity_limits_undo_steps() {
+        let mut program = Program::init(&COUNTDOWN);
+        program.enable_undo_log(2);
+        program.run(vec![3]).unwrap();
+        assert_eq!(program.undo_log().unwrap().entries.len(), 2);
+        assert!(program.step_back());
+        assert!(program.step_back());
+        assert!(!program.step_back());
+    }
+
+    #[test]
+    fn queued_inputs_are_restored() {
+        let mut program = Program::init(&[3, 0, 4, 0, 99]);
+        program.enable_undo_log(10);
+        program.provide_input(42);
+        assert_eq!(program.resume(), Ok(RunState::Output(42)));
+        assert!(program.step_back());
+        assert!(program.step_back());
+        assert_eq!(program.read_memory(0), 3);
+        assert_eq!(program.resume(), Ok(RunState::Output(42)));
+    }
+
+    #[test]
+    fn custom_opcodes_with_unresolved_parameters_are_undone() {
+        // the handler never reads its parameter, which refers to a negative address
+        let ignore = OpcodeDefinition { number: 50, name: "IGN", parameter_count: 1, result_parameter: None, reads_input: false };
+        let mut program = ProgramBuilder::new(&[1101, 1, 1, 9, 50, -1, 99]).opcode(ignore, |_| Ok(CustomAction::Continue)).build();
+        program.enable_undo_log(10);
+        program.run(Vec::new()).unwrap();
+        assert_eq!(program.undo_log().unwrap().entries.len(), 2);
+        assert!(program.step_back());
+        assert_eq!(program.instruction_pointer(), 4);
+        assert_eq!(program.read_memory(9), 2);
+        assert!(program.step_back());
+        assert_eq!(program.instruction_pointer(), 0);
+        assert_eq!(program.read_memory(9), 0);
+    }
+}
@@ -0,0 +1,188 @@
+//! Callbacks that are notified whenever instructions access certain memory addresses, see `Program::watch`.
+//!
+//! Only parameters in position or relative mode are reads, fetching an instruction's own words isn't.
+//! Writes done via `Program::set_memory` aren't reported either.
+use std::ops::{Bound, RangeBounds, RangeInclusive};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Which accesses a watchpoint reports.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(&self, access: Access) -> bool {
+        return matches!(
+            (self, access),
+            (WatchKind::ReadWrite, _) | (WatchKind::Read, Access::Read) | (WatchKind::Write, Access::Write)
+        );
+    }
+}
+
+/// A single access to a watched address. For reads, the old and new value are the same.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct WatchEvent {
+    pub instruction_pointer: usize,
+    pub address: usize,
+    pub access: Access,
+    pub old_value: i64,
+    pub new_value: i64,
+}
+
+type Callback = Arc<Mutex<dyn FnMut(&WatchEvent) + Send>>;
+
+#[derive(Clone)]
+struct Watchpoint {
+    id: usize,
+    addresses: RangeInclusive<usize>,
+    kind: WatchKind,
+    callback: Callback,
+}
+
+/// All watchpoints of a program. Clones of a program share the callbacks of the original.
+#[derive(Clone, Default)]
+pub(crate) struct Watchpoints {
+    watchpoints: Vec<Watchpoint>,
+    next_id: usize,
+}
+
+impl Watchpoints {
+    pub(crate) fn add(&mut self, addresses: impl RangeBounds<usize>, kind: WatchKind, callback: Callback) -> usize {
+        let start = match addresses.start_bound() {
+            Bound::Included(start) => Some(*start),
+            Bound::Excluded(start) => start.checked_add(1),
+            Bound::Unbounded => Some(0),
+        };
+        let end = match addresses.end_bound() {
+            Bound::Included(end) => Some(*end),
+            Bound::Excluded(end) => end.checked_sub(1),
+            Bound::Unbounded => Some(usize::MAX),
+        };
+        let addresses = match (start, end) {
+            (Some(start), Some(end)) => start..=end,
+            // no address is watched
+            _ => RangeInclusive::new(1, 0),
+        };
+        let id = self.next_id;
+        self.next_id += 1;
+        self.watchpoints.push(Watchpoint { id, addresses, kind, callback });
+        return id;
+    }
+
+    pub(crate) fn remove(&mut self, id: usize) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| watchpoint.id != id);
+        return self.watchpoints.len() != count;
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        return self.watchpoints.is_empty();
+    }
+
+    pub(crate) fn is_watched(&self, address: usize, access: Access) -> bool {
+        return self
+            .watchpoints
+            .iter()
+            .any(|watchpoint| watchpoint.addresses.contains(&address) && watchpoint.kind.matches(access));
+    }
+
+    pub(crate) fn notify(&self, event: &WatchEvent) {
+        for watchpoint in self.watchpoints.iter() {
+            if watchpoint.addresses.contains(&event.address) && watchpoint.kind.matches(event.access) {
+                let mut callback = watchpoint.callback.lock().expect("watchpoint callback panicked");
+                callback(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Program;
+
+    fn record_events(program: &mut Program, addresses: impl RangeBounds<usize>, kind: WatchKind) -> Arc<Mutex<Vec<WatchEvent>>> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded_events = events.clone();
+        program.watch(addresses, kind, move |event| recorded_events.lock().unwrap().push(*event));
+        return events;
+    }
+
+    #[test]
+    fn reads_and_writes_are_reported() {
+        // [10] = [10] + [11], twice
+        let mut program = Program::init(&[1, 10, 11, 10, 1, 10, 11, 10, 99, 0, 3, 4]);
+        let writes = record_events(&mut program, 10..=10, WatchKind::Write);
+        let reads = record_events(&mut program, 10..12, WatchKind::Read);
+        program.run(Vec::new()).unwrap();
+        let event = |instruction_pointer, address, access, old_value, new_value| WatchEvent {
+            instruction_pointer,
+            address,
+            access,
+            old_value,
+            new_value,
+        };
+        assert_eq!(
+            *writes.lock().unwrap(),
+            [event(0, 10, Access::Write, 3, 7), event(4, 10, Access::Write, 7, 11)]
+        );
+        assert_eq!(
+            *reads.lock().unwrap(),
+            [
+                event(0, 10, Access::Read, 3, 3),
+                event(0, 11, Access::Read, 4, 4),
+                event(4, 10, Access::Read, 7, 7),
+                event(4, 11, Access::Read, 4, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn immediate_parameters_and_unwatched_addresses_are_ignored() {
+        let mut program = Program::init(&[1101, 1, 2, 7, 4, 8, 99, 0, 5]);
+        let events = record_events(&mut program, 0..7, WatchKind::ReadWrite);
+        assert_eq!(program.run(Vec::new()).unwrap(), [5]);
+        assert!(events.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn removed_watchpoints_are_not_notified() {
+        let mut program = Program::init(&[3, 5, 3, 5, 99, 0]);
+        let events = Arc::new(Mutex::new(0));
+        let counter = events.clone();
+        let id = program.watch(.., WatchKind::Write, move |_| *counter.lock().unwrap() += 1);
+        program.step(Some(1)).unwrap();
+        assert!(program.unwatch(id));
+        assert!(!program.unwatch(id));
+        program.step(Some(2)).unwrap();
+        assert_eq!(*events.lock().unwrap(), 1);
+        assert_eq!(program.read_memory(5), 2);
+    }
+
+    #[test]
+    fn relative_mode_accesses_are_watched() {
+        let mut program = Program::init(&[109, 50, 203, 5, 99]);
+        let events = record_events(&mut program, 55..=55, WatchKind::Write);
+        program.run(vec![-1]).unwrap();
+        assert_eq!(events.lock().unwrap()[0].new_value, -1);
+    }
+
+    #[test]
+    fn ranges_up_to_the_last_address() {
+        let mut program = Program::init(&[3, 5, 99]);
+        let all = record_events(&mut program, ..=usize::MAX, WatchKind::Write);
+        let none = record_events(&mut program, ..0, WatchKind::Write);
+        program.run(vec![4]).unwrap();
+        assert_eq!(all.lock().unwrap().len(), 1);
+        assert!(none.lock().unwrap().is_empty());
+    }
+}